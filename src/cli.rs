@@ -0,0 +1,107 @@
+use crate::network_simulator::NetworkConditions;
+use bones_framework::prelude::*;
+use std::path::PathBuf;
+
+/// The input delay used by auto matchmaking when none is specified
+const DEFAULT_AUTO_MATCHMAKING_INPUT_DELAY: usize = 2;
+/// The seed used by the sync test when none is specified
+const DEFAULT_SYNC_TEST_SEED: u32 = 1;
+
+/// Command-line arguments of the game, inserted as a shared resource so every session can read them
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct CliArgs {
+    /// Skips the menu and starts searching for an online match immediately
+    pub auto_matchmaking: bool,
+    /// The input delay frames used by auto matchmaking
+    pub input_delay_frames: usize,
+    /// If set, the inputs of both players are written to a file every frame. An empty path
+    /// uses the default location in the `logs` folder.
+    pub inputs_logging: Option<PathBuf>,
+    /// Overrides the matchmaking server node id from `game.yaml`
+    pub matchmaker: Option<String>,
+    /// If set, skips the menu and starts a sync test with random inputs generated from this seed.
+    /// Cleared once the sync test has been started.
+    pub sync_test: Option<u32>,
+    /// If set, skips the menu and plays the given replay file. Cleared once it has been started.
+    pub replay: Option<PathBuf>,
+    /// The network conditions initially simulated on the GGRS traffic of online matches
+    pub network_conditions: NetworkConditions,
+}
+
+impl CliArgs {
+    /// Parses the arguments the game was launched with
+    pub fn from_env() -> Self {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Parses the given arguments, unknown arguments are reported and ignored
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Self {
+        let mut cli_args = CliArgs {
+            input_delay_frames: DEFAULT_AUTO_MATCHMAKING_INPUT_DELAY,
+            ..default()
+        };
+
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--auto-matchmaking" => {
+                    cli_args.auto_matchmaking = true;
+                }
+                "--input-delay" => match args.next().and_then(|value| value.parse().ok()) {
+                    Some(frames) => cli_args.input_delay_frames = frames,
+                    None => eprintln!("--input-delay expects a number of frames"),
+                },
+                "--inputs-logging" => {
+                    // The log file path is optional
+                    let path = args
+                        .next_if(|value| !value.starts_with("--"))
+                        .map(PathBuf::from);
+                    cli_args.inputs_logging = Some(path.unwrap_or_default());
+                }
+                "--sync-test" => {
+                    // The seed is optional
+                    let seed = args
+                        .next_if(|value| !value.starts_with("--"))
+                        .and_then(|value| value.parse().ok());
+                    cli_args.sync_test = Some(seed.unwrap_or(DEFAULT_SYNC_TEST_SEED));
+                }
+                "--replay" => match args.next() {
+                    Some(path) => cli_args.replay = Some(PathBuf::from(path)),
+                    None => eprintln!("--replay expects a replay file"),
+                },
+                "--sim-latency" => match args.next().and_then(|value| value.parse().ok()) {
+                    Some(ms) => cli_args.network_conditions.latency_ms = ms,
+                    None => eprintln!("--sim-latency expects a number of milliseconds"),
+                },
+                "--sim-jitter" => match args.next().and_then(|value| value.parse().ok()) {
+                    Some(ms) => cli_args.network_conditions.jitter_ms = ms,
+                    None => eprintln!("--sim-jitter expects a number of milliseconds"),
+                },
+                "--sim-loss" => match args.next().and_then(|value| value.parse::<f32>().ok()) {
+                    Some(percent) => {
+                        cli_args.network_conditions.packet_loss = percent.clamp(0.0, 100.0) / 100.0
+                    }
+                    None => eprintln!("--sim-loss expects a percentage"),
+                },
+                "--sim-reorder" => match args.next().and_then(|value| value.parse::<f32>().ok()) {
+                    Some(percent) => {
+                        cli_args.network_conditions.reorder = percent.clamp(0.0, 100.0) / 100.0
+                    }
+                    None => eprintln!("--sim-reorder expects a percentage"),
+                },
+                "--matchmaker" => match args.next() {
+                    Some(node_id) => cli_args.matchmaker = Some(node_id),
+                    None => eprintln!("--matchmaker expects a node id"),
+                },
+                _ => eprintln!("Ignoring unknown argument: {}", arg),
+            }
+        }
+
+        cli_args
+    }
+
+    /// Returns the matchmaking server to use, preferring the command-line override
+    pub fn matchmaking_server<'a>(&'a self, default_server: &'a str) -> &'a str {
+        self.matchmaker.as_deref().unwrap_or(default_server)
+    }
+}
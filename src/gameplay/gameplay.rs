@@ -1,46 +1,235 @@
 use super::{
-    ball_movement, ball_net_collision, ball_player_collision, create_circle_path, gameplay_ui::*,
-    player_movement, update_ball_visibility, Ball, Floor, LocalPlayer, Net, Player,
+    ball_movement, ball_net_collision, ball_player_collision, bot_controller, create_circle_path,
+    gameplay_ui::*, handle_pause_input, handle_rematch_votes, inputs_logging_startup,
+    log_match_inputs, player_movement, record_rally_history, record_replay_inputs,
+    record_state_checksum, reset_touches_on_net_cross, save_replay_on_match_end, sync_transforms,
+    update_ball_visibility, update_match_phase, Ball, BotDifficultyMeta, BotPlayers, Fixed, Floor,
+    InputsLogger, LocalPlayer, MatchPhase, Net, PauseState, Player, RallyHistory, RematchVotes,
+    ReplayRecorder, StateChecksums,
 };
+use crate::replay::Replay;
 use crate::{
     input::{MatchInputs, PlayerControlMapping, PlayerInputCollector},
-    menu::*,
-    GameMeta, SessionNames,
+    networking::MAX_PREDICTION_WINDOW,
+    SessionNames,
 };
+use bones_framework::networking::NetworkMatchSocket;
 use bones_framework::prelude::*;
 
-/// The score required to win the match
-pub const TARGET_SCORE: u32 = 15;
-/// The Y-coordinate of the ground level
-pub const GROUND_LEVEL: f32 = -244.0;
-/// The gravity constant for the game
-pub const GRAVITY: f32 = 0.175 * 1.5 * 1.5;
-/// The movement speed of the players
-pub const MOVE_SPEED: f32 = 4.35;
-/// The initial velocity of a player's jump
-pub const JUMP_VELOCITY: f32 = 7.5 * 1.5;
-/// The left boundary of the playfield
-pub const LEFT_BOUNDARY: f32 = -530.0;
-/// The right boundary of the playfield
-pub const RIGHT_BOUNDARY: f32 = 510.0;
-/// The center boundary of the playfield
-pub const CENTER_BOUNDARY: f32 = 0.0;
-/// The width of the net
-pub const NET_WIDTH: f32 = 10.0;
-/// The height of the net
-pub const NET_HEIGHT: f32 = 62.00;
-/// The width of a player sprite
-pub const PLAYER_WIDTH: f32 = 90.0;
-/// The height of a player sprite
-pub const PLAYER_HEIGHT: f32 = 14.0;
-/// The radius of the ball
-pub const BALL_RADIUS: f32 = 10.0;
-/// The bounce factor for the ball when hitting surfaces
-pub const BALL_BOUNCE_FACTOR: f32 = 0.8;
-/// The bounce factor for the ball when hitting players
-pub const PLAYER_BOUNCE_FACTOR: f32 = 1.2;
-/// The maximum speed of the ball
-pub const MAX_BALL_SPEED: f32 = 11.25 * 1.5;
+/// Physics and tuning values of the gameplay, loaded from `game.yaml`.
+///
+/// A copy is inserted into the gameplay session when a match starts, so the values are rolled back
+/// along with the world and can't change mid-match. Online peers agree on the same copy during
+/// the match-start handshake.
+#[derive(HasSchema, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub struct PhysicsMeta {
+    /// The Y-coordinate of the ground level
+    pub ground_level: f32,
+    /// The Y-coordinate of the ceiling the ball bounces off
+    pub ceiling_level: f32,
+    /// The gravity applied every frame
+    pub gravity: f32,
+    /// The movement speed of the players
+    pub move_speed: f32,
+    /// The initial velocity of a player's jump
+    pub jump_velocity: f32,
+    /// The left boundary of the playfield
+    pub left_boundary: f32,
+    /// The right boundary of the playfield
+    pub right_boundary: f32,
+    /// The center boundary of the playfield
+    pub center_boundary: f32,
+    /// The width of the net
+    pub net_width: f32,
+    /// The height of the net
+    pub net_height: f32,
+    /// The Y-coordinate of the bottom of the net
+    pub net_base_level: f32,
+    /// The width of a player sprite
+    pub player_width: f32,
+    /// The height of a player sprite
+    pub player_height: f32,
+    /// The radius of the ball
+    pub ball_radius: f32,
+    /// The bounce factor for the ball when hitting surfaces
+    pub ball_bounce_factor: f32,
+    /// The bounce factor for the ball when hitting players
+    pub player_bounce_factor: f32,
+    /// The maximum speed of the ball
+    pub max_ball_speed: f32,
+    /// The distance from the center at which players and the ball spawn
+    pub spawn_offset_x: f32,
+}
+
+impl Default for PhysicsMeta {
+    /// The original tuning values of the game
+    fn default() -> Self {
+        Self {
+            ground_level: -244.0,
+            ceiling_level: 290.0,
+            gravity: 0.175 * 1.5 * 1.5,
+            move_speed: 4.35,
+            jump_velocity: 7.5 * 1.5,
+            left_boundary: -530.0,
+            right_boundary: 510.0,
+            center_boundary: 0.0,
+            net_width: 10.0,
+            net_height: 62.0,
+            net_base_level: -259.0,
+            player_width: 90.0,
+            player_height: 14.0,
+            ball_radius: 10.0,
+            ball_bounce_factor: 0.8,
+            player_bounce_factor: 1.2,
+            max_ball_speed: 11.25 * 1.5,
+            spawn_offset_x: 290.0,
+        }
+    }
+}
+
+impl PhysicsMeta {
+    /// The length of the bytes created by `to_bytes`
+    pub const BYTES_LEN: usize = 4 * 18;
+
+    /// Serializes the values into bytes, used to exchange the config between peers
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.float_values()
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    /// Deserializes values created with `to_bytes`, returns None if the bytes are malformed or
+    /// the values can't be simulated (see `is_valid`)
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::BYTES_LEN {
+            return None;
+        }
+        let mut chunks = bytes
+            .chunks_exact(4)
+            .map(|chunk| [chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let mut physics = Self::default();
+        for value in physics.float_values_mut() {
+            *value = f32::from_le_bytes(chunks.next()?);
+        }
+        physics.is_valid().then_some(physics)
+    }
+
+    /// Returns false if a value is not finite or a speed isn't positive, which the fixed-point
+    /// simulation would overflow or divide by zero with
+    pub fn is_valid(&self) -> bool {
+        self.float_values().iter().all(|value| value.is_finite())
+            && [self.move_speed, self.jump_velocity, self.max_ball_speed]
+                .iter()
+                .all(|&speed| speed > 0.0)
+    }
+
+    /// Returns a hash of the config, used to tell apart matches played with different physics
+    pub fn config_hash(&self) -> u64 {
+        // FNV-1a, stable across platforms and builds unlike the std hasher
+        self.to_bytes()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    /// Converts the values to fixed-point for use in the simulation
+    pub fn to_fixed(&self) -> FixedPhysics {
+        FixedPhysics {
+            ground_level: Fixed::from_f32(self.ground_level),
+            ceiling_level: Fixed::from_f32(self.ceiling_level),
+            gravity: Fixed::from_f32(self.gravity),
+            move_speed: Fixed::from_f32(self.move_speed),
+            jump_velocity: Fixed::from_f32(self.jump_velocity),
+            left_boundary: Fixed::from_f32(self.left_boundary),
+            right_boundary: Fixed::from_f32(self.right_boundary),
+            center_boundary: Fixed::from_f32(self.center_boundary),
+            net_width: Fixed::from_f32(self.net_width),
+            net_height: Fixed::from_f32(self.net_height),
+            net_base_level: Fixed::from_f32(self.net_base_level),
+            player_width: Fixed::from_f32(self.player_width),
+            player_height: Fixed::from_f32(self.player_height),
+            ball_radius: Fixed::from_f32(self.ball_radius),
+            ball_bounce_factor: Fixed::from_f32(self.ball_bounce_factor),
+            player_bounce_factor: Fixed::from_f32(self.player_bounce_factor),
+            max_ball_speed: Fixed::from_f32(self.max_ball_speed),
+            spawn_offset_x: Fixed::from_f32(self.spawn_offset_x),
+        }
+    }
+
+    /// Returns all the float values in a fixed order
+    fn float_values(&self) -> [f32; 18] {
+        [
+            self.ground_level,
+            self.ceiling_level,
+            self.gravity,
+            self.move_speed,
+            self.jump_velocity,
+            self.left_boundary,
+            self.right_boundary,
+            self.center_boundary,
+            self.net_width,
+            self.net_height,
+            self.net_base_level,
+            self.player_width,
+            self.player_height,
+            self.ball_radius,
+            self.ball_bounce_factor,
+            self.player_bounce_factor,
+            self.max_ball_speed,
+            self.spawn_offset_x,
+        ]
+    }
+
+    /// Returns mutable references to all the float values, in the same order as `float_values`
+    fn float_values_mut(&mut self) -> [&mut f32; 18] {
+        [
+            &mut self.ground_level,
+            &mut self.ceiling_level,
+            &mut self.gravity,
+            &mut self.move_speed,
+            &mut self.jump_velocity,
+            &mut self.left_boundary,
+            &mut self.right_boundary,
+            &mut self.center_boundary,
+            &mut self.net_width,
+            &mut self.net_height,
+            &mut self.net_base_level,
+            &mut self.player_width,
+            &mut self.player_height,
+            &mut self.ball_radius,
+            &mut self.ball_bounce_factor,
+            &mut self.player_bounce_factor,
+            &mut self.max_ball_speed,
+            &mut self.spawn_offset_x,
+        ]
+    }
+}
+
+/// The fixed-point counterpart of PhysicsMeta used by the simulation, see PhysicsMeta for the docs
+#[derive(Clone, Copy, Debug)]
+pub struct FixedPhysics {
+    pub ground_level: Fixed,
+    pub ceiling_level: Fixed,
+    pub gravity: Fixed,
+    pub move_speed: Fixed,
+    pub jump_velocity: Fixed,
+    pub left_boundary: Fixed,
+    pub right_boundary: Fixed,
+    pub center_boundary: Fixed,
+    pub net_width: Fixed,
+    pub net_height: Fixed,
+    pub net_base_level: Fixed,
+    pub player_width: Fixed,
+    pub player_height: Fixed,
+    pub ball_radius: Fixed,
+    pub ball_bounce_factor: Fixed,
+    pub player_bounce_factor: Fixed,
+    pub max_ball_speed: Fixed,
+    pub spawn_offset_x: Fixed,
+}
 
 /// Metadata for gameplay
 #[derive(HasSchema, Default, Clone, Debug)]
@@ -50,39 +239,330 @@ pub struct GameplayMeta {
     pub player_sprite: Handle<Image>,
     pub floor_sprite: Handle<Image>,
     pub net_sprite: Handle<Image>,
+    /// The physics and tuning values of the gameplay
+    pub physics: PhysicsMeta,
+    /// The rules of a match
+    pub rules: MatchRules,
+    /// The difficulty levels selectable when playing against the CPU
+    pub bot_difficulties: SVec<BotDifficultyMeta>,
+}
+
+/// The sprites used by the gameplay session, copied from GameplayMeta when the match starts so that
+/// the session doesn't depend on loaded assets (e.g. when running headless)
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct GameplaySprites {
+    pub player_sprite: Handle<Image>,
+    pub floor_sprite: Handle<Image>,
+    pub net_sprite: Handle<Image>,
+}
+
+impl GameplaySprites {
+    /// Creates GameplaySprites from the gameplay metadata
+    pub fn from_meta(meta: &GameplayMeta) -> Self {
+        Self {
+            player_sprite: meta.player_sprite,
+            floor_sprite: meta.floor_sprite,
+            net_sprite: meta.net_sprite,
+        }
+    }
+}
+
+/// The rules of a match, loaded from `game.yaml` and agreed on by online peers like PhysicsMeta
+#[derive(HasSchema, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub struct MatchRules {
+    /// The points required to win a set
+    pub target_score: u32,
+    /// The maximum number of consecutive touches on one side before the point goes to the
+    /// opponent, 0 for unlimited
+    pub max_touches: u32,
+    /// Whether the same player may touch the ball twice in a row
+    pub allow_double_touch: bool,
+    /// Whether a set can only be won with a lead of at least two points
+    pub win_by_two: bool,
+    /// The points at which a set is won regardless of the lead, 0 for no cap
+    pub point_cap: u32,
+    /// The number of sets the match is played over (best of 1, 3, 5, ...)
+    pub best_of_sets: u32,
+    /// The length of the countdown before the first serve and of the pause after every point,
+    /// in frames
+    pub countdown_frames: u32,
+    /// The frames the server may hold the ball before it is served automatically
+    pub serve_timer_frames: u32,
+    /// The number of frames at the end of a rally shown again after a point, 0 for no instant
+    /// replay
+    pub rally_replay_frames: u32,
+    /// How many times slower than real-time the instant replay is played
+    pub slow_motion_factor: u32,
+    /// Whether the players take turns serving instead of the winner of the last rally serving
+    pub alternate_serve: bool,
+    /// The frames a match may stay paused before it resumes by itself, 0 for no limit
+    pub pause_timeout_frames: u32,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            target_score: 15,
+            max_touches: 3,
+            allow_double_touch: true,
+            win_by_two: false,
+            point_cap: 0,
+            best_of_sets: 1,
+            countdown_frames: 90,
+            serve_timer_frames: 180,
+            rally_replay_frames: 150,
+            slow_motion_factor: 2,
+            alternate_serve: false,
+            pause_timeout_frames: 3600,
+        }
+    }
+}
+
+impl MatchRules {
+    /// The length of the bytes created by `to_bytes`
+    pub const BYTES_LEN: usize = 9 * 4 + 3;
+
+    /// Serializes the rules into bytes, used to exchange them between peers
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::BYTES_LEN);
+        for value in [
+            self.target_score,
+            self.max_touches,
+            self.point_cap,
+            self.best_of_sets,
+            self.countdown_frames,
+            self.serve_timer_frames,
+            self.rally_replay_frames,
+            self.slow_motion_factor,
+            self.pause_timeout_frames,
+        ] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.push(self.allow_double_touch as u8);
+        bytes.push(self.win_by_two as u8);
+        bytes.push(self.alternate_serve as u8);
+        bytes
+    }
+
+    /// Deserializes rules created with `to_bytes`, returns None if the bytes are malformed
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::BYTES_LEN {
+            return None;
+        }
+        let read_u32 = |idx: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(
+                bytes[idx * 4..idx * 4 + 4].try_into().ok()?,
+            ))
+        };
+        Some(Self {
+            target_score: read_u32(0)?,
+            max_touches: read_u32(1)?,
+            point_cap: read_u32(2)?,
+            best_of_sets: read_u32(3)?,
+            countdown_frames: read_u32(4)?,
+            serve_timer_frames: read_u32(5)?,
+            rally_replay_frames: read_u32(6)?,
+            slow_motion_factor: read_u32(7)?,
+            pause_timeout_frames: read_u32(8)?,
+            allow_double_touch: bytes[36] != 0,
+            win_by_two: bytes[37] != 0,
+            alternate_serve: bytes[38] != 0,
+        })
+    }
+
+    /// The number of sets a player needs to win the match
+    pub fn sets_to_win(&self) -> u32 {
+        self.best_of_sets.max(1) / 2 + 1
+    }
+
+    /// The length of the instant replay phase, 0 if there is none
+    pub fn instant_replay_frames(&self) -> u32 {
+        self.rally_replay_frames * self.slow_motion_factor.max(1)
+    }
 }
 
 /// Represents the current state of the match
 #[derive(HasSchema, Clone, Debug, Default)]
 pub struct MatchState {
+    /// The points of each player in the current set
     player_scores: [u32; 2],
-    target_score: u32,
+    /// The sets won by each player
+    set_scores: [u32; 2],
+    /// The winner of a match the opponent left before it was over
+    forfeit_winner: Option<usize>,
+    rules: MatchRules,
+    /// The player serving the current rally
+    server: usize,
+    /// The player who touched the ball last during the current rally
+    last_touch_player: Option<usize>,
+    /// The number of consecutive touches on the side of the last touch
+    touch_count: u32,
 }
 
 impl MatchState {
-    /// Creates a new MatchState with the given target score
-    pub fn new(target_score: u32) -> Self {
+    /// Creates a new MatchState with the given rules
+    pub fn new(rules: MatchRules) -> Self {
         Self {
             player_scores: [0, 0],
-            target_score,
+            set_scores: [0, 0],
+            forfeit_winner: None,
+            rules,
+            server: 0,
+            last_touch_player: None,
+            touch_count: 0,
         }
     }
 
-    /// Gets the score of the specified player
+    /// Returns the rules of the match
+    pub fn rules(&self) -> &MatchRules {
+        &self.rules
+    }
+
+    /// Gets the points of the specified player in the current set
     pub fn get_player_score(&self, player_idx: usize) -> u32 {
         self.player_scores[player_idx]
     }
 
-    /// Increments the score of the specified player
+    /// Gets the number of sets won by the specified player
+    pub fn get_set_score(&self, player_idx: usize) -> u32 {
+        self.set_scores[player_idx]
+    }
+
+    /// Returns the number of the set being played, starting at 1. Once the match is won the
+    /// deciding set stays the current one.
+    pub fn current_set(&self) -> u32 {
+        self.side_switches() + 1
+    }
+
+    /// Returns the number of times the players switched sides, after every set except the one
+    /// which won the match
+    fn side_switches(&self) -> u32 {
+        let sets_played = self.set_scores[0] + self.set_scores[1];
+        let sets_to_win = self.rules.sets_to_win();
+        if self.set_scores.iter().any(|&sets| sets >= sets_to_win) {
+            sets_played - 1
+        } else {
+            sets_played
+        }
+    }
+
+    /// Increments the points of the specified player in the current set
     pub fn increment_player_score(&mut self, player_idx: usize) {
         self.player_scores[player_idx] += 1;
     }
 
+    /// Returns the player serving the current rally
+    pub fn server(&self) -> usize {
+        self.server
+    }
+
+    /// Awards a point to the specified player, ending the current rally and choosing the next
+    /// server. Finishes the set if the point won it, the points of the final set are kept so
+    /// they can still be displayed.
+    pub fn award_point(&mut self, player_idx: usize) {
+        self.increment_player_score(player_idx);
+        self.reset_touches();
+        self.server = if self.rules.alternate_serve {
+            1 - self.server
+        } else {
+            player_idx
+        };
+
+        if let Some(set_winner) = self.check_for_set_winner() {
+            self.set_scores[set_winner] += 1;
+            if !self.is_finished() {
+                self.player_scores = [0, 0];
+            }
+        }
+    }
+
+    /// Records a touch of the ball by the specified player.
+    ///
+    /// Returns the index of the opponent if the touch broke the touch rules, in which case the
+    /// point should be awarded to them.
+    pub fn register_touch(&mut self, player_idx: usize) -> Option<usize> {
+        if self.last_touch_player == Some(player_idx) {
+            if !self.rules.allow_double_touch {
+                return Some(1 - player_idx);
+            }
+            self.touch_count += 1;
+        } else {
+            self.last_touch_player = Some(player_idx);
+            self.touch_count = 1;
+        }
+
+        if self.rules.max_touches > 0 && self.touch_count > self.rules.max_touches {
+            return Some(1 - player_idx);
+        }
+        None
+    }
+
+    /// Returns the player who touched the ball last during the current rally, if any
+    pub fn last_touch_player(&self) -> Option<usize> {
+        self.last_touch_player
+    }
+
+    /// Returns the number of consecutive touches on the side of the last touch
+    pub fn touch_count(&self) -> u32 {
+        self.touch_count
+    }
+
+    /// Resets the touch count, called when the ball crosses the net or the rally ends
+    pub fn reset_touches(&mut self) {
+        self.last_touch_player = None;
+        self.touch_count = 0;
+    }
+
+    /// Returns the side of the net the specified player plays on (0 for left, 1 for right).
+    /// Players switch sides after every set, but not after the final one.
+    pub fn side_of(&self, player_idx: usize) -> usize {
+        (player_idx + self.side_switches() as usize) % 2
+    }
+
+    /// Returns the player playing on the given side of the net (0 for left, 1 for right)
+    pub fn player_on_side(&self, side: usize) -> usize {
+        // Switching sides is its own inverse
+        self.side_of(side)
+    }
+
+    /// Checks if a player has won the current set and returns their index if so
+    pub fn check_for_set_winner(&self) -> Option<usize> {
+        (0..2).find(|&player_idx| {
+            let score = self.player_scores[player_idx];
+            let opponent_score = self.player_scores[1 - player_idx];
+            if self.rules.point_cap > 0 && score >= self.rules.point_cap {
+                return true;
+            }
+            let required_lead = if self.rules.win_by_two { 2 } else { 1 };
+            score >= self.rules.target_score && score >= opponent_score + required_lead
+        })
+    }
+
+    /// Returns true if the specified player is ahead in sets, or in points when the sets are tied
+    pub fn is_leading(&self, player_idx: usize) -> bool {
+        let opponent_idx = 1 - player_idx;
+        let standing = |idx: usize| (self.set_scores[idx], self.player_scores[idx]);
+        standing(player_idx) > standing(opponent_idx)
+    }
+
+    /// Ends the match with the specified player winning by forfeit
+    pub fn forfeit(&mut self, winner_idx: usize) {
+        self.forfeit_winner = Some(winner_idx);
+    }
+
+    /// Returns the winner of the match if it was won by forfeit
+    pub fn forfeit_winner(&self) -> Option<usize> {
+        self.forfeit_winner
+    }
+
     /// Checks if there's a winner and returns their index if so
     pub fn check_for_match_winner(&self) -> Option<usize> {
-        self.player_scores
-            .iter()
-            .position(|&score| score >= self.target_score)
+        if self.forfeit_winner.is_some() {
+            return self.forfeit_winner;
+        }
+        let sets_to_win = self.rules.sets_to_win();
+        self.set_scores.iter().position(|&sets| sets >= sets_to_win)
     }
 
     /// Checks if the match is finished
@@ -91,6 +571,28 @@ impl MatchState {
     }
 }
 
+/// The number of frames simulated so far, rolled back along with the rest of the world
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct SimulationFrame {
+    pub frame: u32,
+}
+
+impl SimulationFrame {
+    /// Returns the last frame which can no longer be rolled back.
+    ///
+    /// The session runners don't expose the confirmed frame of their GGRS session, but GGRS never
+    /// predicts further than the prediction window, so every frame older than that is confirmed.
+    pub fn confirmed_frame(&self) -> u32 {
+        self.frame
+            .saturating_sub(MAX_PREDICTION_WINDOW.unwrap_or(0) as u32 + 1)
+    }
+}
+
+/// Advances the simulation frame counter, runs first every frame
+fn advance_simulation_frame(mut simulation_frame: ResMut<SimulationFrame>) {
+    simulation_frame.frame += 1;
+}
+
 /// Plugin for managing the gameplay session
 pub struct GameplayPlugin {
     pub session_runner: Box<dyn SessionRunner>,
@@ -99,21 +601,74 @@ pub struct GameplayPlugin {
 impl GameplayPlugin {
     /// Starts gameplay by initializing both a gameplay and gameplay_ui session
     pub fn start_gameplay_session(
-        mut sessions: ResMut<Sessions>,
+        sessions: &mut Sessions,
         session_runner: Box<dyn SessionRunner>,
-        local_player_idx: u32,
+        local_player_idx: Option<u32>,
+        bot_players: BotPlayers,
+        physics: PhysicsMeta,
+        rules: MatchRules,
+        sprites: GameplaySprites,
+        socket: Option<NetworkMatchSocket>,
+        replay_input_delay: Option<usize>,
     ) {
         // First setup the gameplay ui session
-        initialize_gameplay_ui_session(&mut sessions);
+        initialize_gameplay_ui_session(sessions, socket);
 
-        // Setup gameplay session with resources that require inputs
         let gameplay_session = sessions.create(SessionNames::GAMEPLAY);
+        Self::setup_gameplay_session(
+            gameplay_session,
+            session_runner,
+            local_player_idx,
+            bot_players,
+            physics,
+            rules,
+            sprites,
+            replay_input_delay,
+        );
+    }
+
+    /// Inserts the resources that require inputs into the given session and installs the plugin.
+    /// Doesn't depend on any other session, so it can also be used to simulate headless.
+    /// Spectators have no `local_player_idx`, so no LocalPlayer is inserted.
+    /// If `replay_input_delay` is set, the match is saved as a replay recorded with that input
+    /// delay once it ends.
+    pub fn setup_gameplay_session(
+        gameplay_session: &mut Session,
+        session_runner: Box<dyn SessionRunner>,
+        local_player_idx: Option<u32>,
+        bot_players: BotPlayers,
+        physics: PhysicsMeta,
+        rules: MatchRules,
+        sprites: GameplaySprites,
+        replay_input_delay: Option<usize>,
+    ) {
+        if let (Some(input_delay), Some(local_player_idx)) = (replay_input_delay, local_player_idx)
+        {
+            let replay = Replay::new(
+                local_player_idx,
+                &physics,
+                rules.clone(),
+                input_delay as u32,
+                &bot_players,
+            );
+            gameplay_session
+                .world
+                .insert_resource(ReplayRecorder::new(replay));
+        }
         gameplay_session
             .world
-            .insert_resource(MatchState::new(TARGET_SCORE));
-        gameplay_session.world.insert_resource(LocalPlayer {
-            idx: local_player_idx,
-        });
+            .insert_resource(MatchPhase::new(&rules));
+        gameplay_session
+            .world
+            .insert_resource(MatchState::new(rules));
+        if let Some(local_player_idx) = local_player_idx {
+            gameplay_session.world.insert_resource(LocalPlayer {
+                idx: local_player_idx,
+            });
+        }
+        gameplay_session.world.insert_resource(bot_players);
+        gameplay_session.world.insert_resource(physics);
+        gameplay_session.world.insert_resource(sprites);
 
         // Install the gameplay plugin
         let gameplay_plugin = GameplayPlugin { session_runner };
@@ -128,17 +683,39 @@ impl SessionPlugin for GameplayPlugin {
         session.world.init_resource::<MatchInputs>();
         session.world.init_resource::<PlayerInputCollector>();
         session.world.init_resource::<PlayerControlMapping>();
+        session.world.init_resource::<BotPlayers>();
+        session.world.init_resource::<SimulationFrame>();
+        session.world.init_resource::<InputsLogger>();
+        session.world.init_resource::<StateChecksums>();
+        session.world.init_resource::<RematchVotes>();
+        session.world.init_resource::<PauseState>();
+        session.world.init_resource::<ReplayRecorder>();
+        session.world.init_resource::<RallyHistory>();
 
         // Add default plugin + systems
         session.install_plugin(DefaultSessionPlugin);
+        session.add_startup_system(gameplay_startup);
+        if session.world.get_resource::<LocalPlayer>().is_some() {
+            session.add_startup_system(inputs_logging_startup);
+        }
         session
-            .add_startup_system(gameplay_startup)
+            .add_system_to_stage(Update, advance_simulation_frame)
+            .add_system_to_stage(Update, record_replay_inputs)
+            .add_system_to_stage(Update, handle_pause_input)
+            .add_system_to_stage(Update, bot_controller)
+            .add_system_to_stage(Update, log_match_inputs)
             .add_system_to_stage(Update, player_movement)
             .add_system_to_stage(Update, ball_movement)
             .add_system_to_stage(Update, ball_player_collision)
             .add_system_to_stage(Update, ball_net_collision)
+            .add_system_to_stage(Update, update_match_phase)
+            .add_system_to_stage(Update, handle_rematch_votes)
+            .add_system_to_stage(Update, reset_touches_on_net_cross)
+            .add_system_to_stage(Update, sync_transforms)
+            .add_system_to_stage(Update, record_rally_history)
             .add_system_to_stage(Update, update_ball_visibility)
-            .add_system_to_stage(Update, handle_escape);
+            .add_system_to_stage(Update, record_state_checksum)
+            .add_system_to_stage(Update, save_replay_on_match_end);
 
         session.runner = self.session_runner;
     }
@@ -155,7 +732,8 @@ fn gameplay_startup(
     mut floors: CompMut<Floor>,
     mut nets: CompMut<Net>,
     mut paths: CompMut<Path2d>,
-    meta: Root<GameMeta>,
+    gameplay_sprites: Res<GameplaySprites>,
+    physics: Res<PhysicsMeta>,
 ) {
     // Create and set up the camera
     let camera_ent = spawn_default_camera(&mut entities, &mut transforms, &mut cameras);
@@ -172,7 +750,7 @@ fn gameplay_startup(
     sprites.insert(
         floor_ent,
         Sprite {
-            image: meta.gameplay.floor_sprite,
+            image: gameplay_sprites.floor_sprite,
             ..default()
         },
     );
@@ -187,28 +765,35 @@ fn gameplay_startup(
     sprites.insert(
         net_ent,
         Sprite {
-            image: meta.gameplay.net_sprite,
+            image: gameplay_sprites.net_sprite,
             ..default()
         },
     );
-    transforms.insert(net_ent, Transform::from_translation(vec3(0.0, -259.0, 0.0)));
+    transforms.insert(
+        net_ent,
+        Transform::from_translation(vec3(physics.center_boundary, physics.net_base_level, 0.0)),
+    );
+
+    // Players and the ball are simulated in fixed-point, their Transforms are synced from it
+    let fixed_physics = physics.to_fixed();
 
     // Create Player 1 (left side)
     let player1_ent = entities.create();
     transforms.insert(
         player1_ent,
-        Transform::from_translation(vec3(-290.0, GROUND_LEVEL, 0.0)),
+        Transform::from_translation(vec3(-physics.spawn_offset_x, physics.ground_level, 0.0)),
     );
     sprites.insert(
         player1_ent,
         Sprite {
-            image: meta.gameplay.player_sprite,
+            image: gameplay_sprites.player_sprite,
             ..default()
         },
     );
     players.insert(
         player1_ent,
         Player {
+            position: Player::spawn_position(0, &fixed_physics),
             idx: 0,
             ..default()
         },
@@ -221,12 +806,12 @@ fn gameplay_startup(
     let player2_ent = entities.create();
     transforms.insert(
         player2_ent,
-        Transform::from_translation(vec3(290.0, GROUND_LEVEL, 0.0)),
+        Transform::from_translation(vec3(physics.spawn_offset_x, physics.ground_level, 0.0)),
     );
     sprites.insert(
         player2_ent,
         Sprite {
-            image: meta.gameplay.player_sprite,
+            image: gameplay_sprites.player_sprite,
             flip_x: true,
             ..default()
         },
@@ -234,6 +819,7 @@ fn gameplay_startup(
     players.insert(
         player2_ent,
         Player {
+            position: Player::spawn_position(1, &fixed_physics),
             idx: 1,
             ..default()
         },
@@ -244,17 +830,18 @@ fn gameplay_startup(
 
     // Create the ball
     let ball_ent = entities.create();
-    let mut ball_transform = Transform::from_translation(vec3(-290.0, 0.0, 0.0));
-    let mut ball = Ball {
-        velocity: Vec2::ZERO,
-    };
-    ball.reset(false, &mut ball_transform);
-    transforms.insert(ball_ent, ball_transform);
+    // The first serve is held above the left player
+    let mut ball = Ball::default();
+    ball.hold_at(-fixed_physics.spawn_offset_x);
+    transforms.insert(
+        ball_ent,
+        Transform::from_translation(ball.position.to_vec2().extend(0.0)),
+    );
     balls.insert(ball_ent, ball);
     paths.insert(
         ball_ent,
         create_circle_path(
-            BALL_RADIUS,
+            physics.ball_radius,
             Color::Rgba {
                 red: 1.0,
                 green: 1.0,
@@ -264,21 +851,3 @@ fn gameplay_startup(
         ),
     );
 }
-
-/// Handles the escape key press to return to the main menu
-fn handle_escape(
-    match_inputs: Res<MatchInputs>,
-    mut sessions: ResMut<Sessions>,
-    mut session_options: ResMut<SessionOptions>,
-) {
-    for player_idx in 0..2 {
-        let player_control = match_inputs.get_control(player_idx);
-        if player_control.esc_start_just_pressed {
-            session_options.delete = true;
-            sessions
-                .create(SessionNames::MAIN_MENU)
-                .install_plugin(menu_plugin);
-            break;
-        }
-    }
-}
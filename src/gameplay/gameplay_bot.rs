@@ -0,0 +1,201 @@
+use super::{gameplay::*, Ball, Fixed, FixedVec2, MatchPhase, MatchState, Player};
+use crate::input::MatchInputs;
+use bones_framework::input::PlayerControls;
+use bones_framework::prelude::*;
+use std::collections::VecDeque;
+
+/// The maximum number of frames the bot simulates ahead when predicting the ball
+const MAX_PREDICTION_FRAMES: usize = 600;
+
+/// Metadata for a selectable bot difficulty level
+#[derive(HasSchema, Default, Clone, Debug)]
+#[repr(C)]
+pub struct BotDifficultyMeta {
+    /// The name displayed in the menu
+    pub name: String,
+    /// How many frames old the ball state is when the bot reacts to it
+    pub reaction_delay_frames: u32,
+    /// The maximum distance the predicted landing spot can be off by
+    pub prediction_error: f32,
+    /// The fraction of the full movement speed the bot is allowed to use (0.0 - 1.0)
+    pub max_speed: f32,
+}
+
+/// Represents a bot controlling one of the players
+#[derive(Clone, Debug, Default)]
+pub struct BotController {
+    /// The difficulty settings of the bot
+    pub difficulty: BotDifficultyMeta,
+    /// Ball positions/velocities of the previous frames, used to delay the bot's reactions
+    ball_history: VecDeque<(FixedVec2, FixedVec2)>,
+    /// The current offset applied to the predicted landing spot
+    prediction_offset: Fixed,
+    /// The horizontal direction of the ball during the last frame
+    last_ball_direction: Fixed,
+    /// State of the pseudo random generator, kept deterministic for rollback safety
+    rng_state: u32,
+}
+
+impl BotController {
+    /// Creates a new BotController with the given difficulty
+    pub fn new(difficulty: BotDifficultyMeta) -> Self {
+        Self {
+            difficulty,
+            ball_history: VecDeque::new(),
+            prediction_offset: Fixed::ZERO,
+            last_ball_direction: Fixed::ZERO,
+            rng_state: 0x9E37_79B9,
+        }
+    }
+
+    /// Returns a pseudo random value in the range -1.0..=1.0
+    fn next_random(&mut self) -> Fixed {
+        // xorshift32
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 17;
+        self.rng_state ^= self.rng_state << 5;
+        let two_bits = Fixed::from_int(2).to_bits();
+        Fixed::from_bits(self.rng_state as i64 * two_bits / u32::MAX as i64) - Fixed::ONE
+    }
+}
+
+/// Holds the bots of the match, indexed by player idx. None means the player is not a bot.
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct BotPlayers {
+    pub bots: [Option<BotController>; 2],
+}
+
+impl BotPlayers {
+    /// Creates a BotPlayers with a single bot controlling the given player
+    pub fn with_bot(player_idx: usize, difficulty: BotDifficultyMeta) -> Self {
+        let mut bot_players = Self::default();
+        bot_players.bots[player_idx] = Some(BotController::new(difficulty));
+        bot_players
+    }
+}
+
+/// Predicts the X-coordinate where the ball will reach the given height, accounting for gravity
+/// and bounces off the walls and the ceiling. Returns None if it doesn't come down in time.
+pub fn predict_ball_landing_x(
+    mut position: FixedVec2,
+    mut velocity: FixedVec2,
+    height: Fixed,
+    physics: &FixedPhysics,
+) -> Option<Fixed> {
+    for _ in 0..MAX_PREDICTION_FRAMES {
+        velocity.y -= physics.gravity;
+        position += velocity;
+
+        if position.x - physics.ball_radius <= physics.left_boundary
+            || position.x + physics.ball_radius >= physics.right_boundary
+        {
+            velocity.x = -velocity.x * physics.ball_bounce_factor;
+            position.x = position.x.clamp(
+                physics.left_boundary + physics.ball_radius,
+                physics.right_boundary - physics.ball_radius,
+            );
+        }
+        if position.y + physics.ball_radius >= physics.ceiling_level {
+            velocity.y = -velocity.y * physics.ball_bounce_factor;
+            position.y = physics.ceiling_level - physics.ball_radius;
+        }
+
+        if velocity.y < Fixed::ZERO && position.y <= height {
+            return Some(position.x);
+        }
+    }
+    None
+}
+
+/// Produces the controls of every bot-controlled player, must run before player_movement
+pub fn bot_controller(
+    entities: Res<Entities>,
+    players: Comp<Player>,
+    balls: Comp<Ball>,
+    mut bot_players: ResMut<BotPlayers>,
+    mut match_inputs: ResMut<MatchInputs>,
+    match_state: Res<MatchState>,
+    match_phase: Res<MatchPhase>,
+    physics: Res<PhysicsMeta>,
+) {
+    if !match_phase.players_can_move() {
+        return;
+    }
+
+    // Find the ball (assuming there's only one ball)
+    let Some((_ball_ent, ball)) = entities.iter_with(&balls).next() else {
+        return;
+    };
+    let physics = physics.to_fixed();
+
+    for (_player_ent, player) in entities.iter_with(&players) {
+        let Some(bot) = bot_players.bots[player.idx].as_mut() else {
+            continue;
+        };
+
+        // Only react to ball states which are older than the reaction delay
+        bot.ball_history.push_back((ball.position, ball.velocity));
+        let reaction_delay = bot.difficulty.reaction_delay_frames as usize;
+        while bot.ball_history.len() > reaction_delay + 1 {
+            bot.ball_history.pop_front();
+        }
+        let (seen_position, seen_velocity) = bot.ball_history[0];
+
+        // Roll a new prediction error every time the ball changes direction
+        let ball_direction = seen_velocity.x.signum();
+        if ball_direction != bot.last_ball_direction {
+            bot.last_ball_direction = ball_direction;
+            bot.prediction_offset =
+                bot.next_random() * Fixed::from_f32(bot.difficulty.prediction_error);
+        }
+
+        let player_position = player.position;
+        let player_x = player_position.x;
+        let side = if match_state.side_of(player.idx) == 0 {
+            -Fixed::ONE
+        } else {
+            Fixed::ONE
+        };
+        let is_on_own_side = |x: Fixed| (x - physics.center_boundary) * side > Fixed::ZERO;
+        let two = Fixed::from_int(2);
+
+        // Stand slightly behind the ball so it bounces towards the opponent
+        let contact_height = physics.ground_level + physics.player_height + physics.ball_radius;
+        let target_x =
+            match predict_ball_landing_x(seen_position, seen_velocity, contact_height, &physics) {
+                Some(landing_x) if is_on_own_side(landing_x) => {
+                    landing_x + bot.prediction_offset + side * physics.player_width / (two * two)
+                }
+                // Return to the middle of our side while waiting
+                _ => {
+                    physics.center_boundary
+                        + side * (physics.right_boundary - physics.center_boundary) / two
+                }
+            };
+
+        // Move towards the target, slowing down when close to avoid overshooting
+        let max_speed = Fixed::from_f32(bot.difficulty.max_speed).clamp(Fixed::ZERO, Fixed::ONE);
+        let movement = (target_x - player_x)
+            .checked_div(physics.move_speed)
+            .unwrap_or(Fixed::ZERO)
+            .clamp(-max_speed, max_speed);
+
+        // Jump when the ball is dropping towards us within reach
+        let rel_x = seen_position.x - player_x;
+        let rel_y = seen_position.y - player_position.y;
+        let jump = is_on_own_side(seen_position.x)
+            && seen_velocity.y < Fixed::ZERO
+            && rel_x.abs() < physics.player_width
+            && rel_y > physics.player_height * Fixed::from_int(3)
+            && rel_y < physics.player_height * Fixed::from_int(10);
+
+        // Fixed values convert to floats exactly, replays and spectators re-run the bot identically
+        let player_control = match_inputs.get_control_mut(player.idx);
+        player_control.left = (-movement).max(Fixed::ZERO).to_f32();
+        player_control.right = movement.max(Fixed::ZERO).to_f32();
+        player_control.left_pressed = player_control.left > 0.0;
+        player_control.right_pressed = player_control.right > 0.0;
+        player_control.jump_just_pressed = jump && !player_control.jump_pressed;
+        player_control.jump_pressed = jump;
+    }
+}
@@ -0,0 +1,238 @@
+use super::{
+    Ball, BotPlayers, MatchPhase, MatchState, PauseState, Player, RematchVotes, SimulationFrame,
+};
+use crate::input::MatchInputs;
+use crate::networking::{MatchSocket, ReliableMessage};
+use crate::SessionNames;
+use bones_framework::networking::SocketTarget;
+use bones_framework::prelude::*;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+
+/// How often (in frames) the state checksum is recorded and exchanged between peers by default
+pub const CHECKSUM_INTERVAL: u32 = 30;
+/// The number of recorded checksums kept around, must cover more than the prediction window
+const CHECKSUM_HISTORY: usize = 16;
+
+/// A hasher which produces identical results on every platform and build (FNV-1a with
+/// little-endian integers), unlike the std DefaultHasher.
+#[derive(Clone, Debug)]
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// The checksum of the gameplay world at a given frame
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateChecksum {
+    pub frame: u32,
+    pub checksum: u64,
+    /// Human readable dump of the hashed state, logged when a desync is detected
+    pub description: String,
+}
+
+/// The recently recorded checksums, rolled back with the world so that re-simulated frames
+/// replace their mispredicted checksums.
+#[derive(HasSchema, Clone, Debug)]
+pub struct StateChecksums {
+    /// How often (in frames) a checksum is recorded
+    pub interval: u32,
+    pub history: VecDeque<StateChecksum>,
+}
+
+impl Default for StateChecksums {
+    fn default() -> Self {
+        Self {
+            interval: CHECKSUM_INTERVAL,
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl StateChecksums {
+    /// Returns the checksum recorded for the given frame, if any
+    pub fn get(&self, frame: u32) -> Option<&StateChecksum> {
+        self.history.iter().find(|entry| entry.frame == frame)
+    }
+}
+
+/// Records the checksum of the players, ball, their transforms and every resource the simulation
+/// writes to (match state, phase, rematch votes, pause menu, bots and match inputs) every
+/// `interval` frames. Runs after every other simulation system.
+pub fn record_state_checksum(
+    entities: Res<Entities>,
+    players: Comp<Player>,
+    balls: Comp<Ball>,
+    transforms: Comp<Transform>,
+    match_state: Res<MatchState>,
+    match_phase: Res<MatchPhase>,
+    rematch_votes: Res<RematchVotes>,
+    pause_state: Res<PauseState>,
+    bot_players: Res<BotPlayers>,
+    match_inputs: Res<MatchInputs>,
+    simulation_frame: Res<SimulationFrame>,
+    mut checksums: ResMut<StateChecksums>,
+) {
+    let frame = simulation_frame.frame;
+    if frame % checksums.interval.max(1) != 0 {
+        return;
+    }
+
+    let mut hasher = StableHasher::default();
+    let mut description = format!(
+        "{:?} {:?} {:?} {:?} {:?} {:?}",
+        *match_state, *match_phase, *rematch_votes, *pause_state, *bot_players, *match_inputs
+    );
+    hasher.write(description.as_bytes());
+
+    for (_ent, (player, transform)) in entities.iter_with((&players, &transforms)) {
+        player.idx.hash(&mut hasher);
+        player.position.hash(&mut hasher);
+        player.velocity.hash(&mut hasher);
+        player.is_grounded.hash(&mut hasher);
+        transform.translation.x.to_bits().hash(&mut hasher);
+        transform.translation.y.to_bits().hash(&mut hasher);
+        description += &format!(
+            " | Player {} pos: {:?} vel: {:?} grounded: {}",
+            player.idx,
+            player.position.to_vec2(),
+            player.velocity.to_vec2(),
+            player.is_grounded
+        );
+    }
+    for (_ent, (ball, transform)) in entities.iter_with((&balls, &transforms)) {
+        ball.position.hash(&mut hasher);
+        ball.velocity.hash(&mut hasher);
+        transform.translation.x.to_bits().hash(&mut hasher);
+        transform.translation.y.to_bits().hash(&mut hasher);
+        description += &format!(
+            " | Ball pos: {:?} vel: {:?}",
+            ball.position.to_vec2(),
+            ball.velocity.to_vec2()
+        );
+    }
+
+    // Replace the entry of a frame which got re-simulated after a rollback
+    checksums.history.retain(|entry| entry.frame != frame);
+    checksums.history.push_back(StateChecksum {
+        frame,
+        checksum: hasher.finish(),
+        description,
+    });
+    while checksums.history.len() > CHECKSUM_HISTORY {
+        checksums.history.pop_front();
+    }
+}
+
+/// Compares the confirmed checksums of both peers, lives in the gameplay_ui session so it isn't
+/// rolled back.
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct DesyncDetector {
+    /// The last frame whose checksum was sent to the other peer
+    last_sent_frame: u32,
+    /// Local checksums waiting for the other peer's checksum of the same frame
+    local: Vec<StateChecksum>,
+    /// Remote checksums waiting for the local checksum of the same frame
+    remote: Vec<StateChecksum>,
+    /// The first frame at which the peers diverged
+    pub desync_frame: Option<u32>,
+}
+
+/// Sends the local checksums of confirmed frames to the other peer and compares them against the
+/// ones received from it.
+pub fn detect_desyncs(
+    sessions: Res<Sessions>,
+    match_socket: Res<MatchSocket>,
+    mut detector: ResMut<DesyncDetector>,
+) {
+    let Some(socket) = &match_socket.socket else {
+        return;
+    };
+    let Some(session) = sessions.get(SessionNames::GAMEPLAY) else {
+        return;
+    };
+    let (Some(simulation_frame), Some(checksums)) = (
+        session.world.get_resource::<SimulationFrame>(),
+        session.world.get_resource::<StateChecksums>(),
+    ) else {
+        return;
+    };
+
+    let confirmed_frame = simulation_frame.confirmed_frame();
+
+    for entry in checksums.history.iter() {
+        if entry.frame > detector.last_sent_frame && entry.frame <= confirmed_frame {
+            let message = ReliableMessage::StateChecksum(entry.clone());
+            socket.send_reliable(SocketTarget::All, &message.to_bytes());
+            detector.last_sent_frame = entry.frame;
+            detector.local.push(entry.clone());
+        }
+    }
+    for message in &match_socket.messages {
+        if let ReliableMessage::StateChecksum(entry) = message {
+            detector.remote.push(entry.clone());
+        }
+    }
+
+    // Compare every frame both peers have a checksum for
+    let DesyncDetector {
+        local,
+        remote,
+        desync_frame,
+        ..
+    } = &mut *detector;
+    local.retain(|local_entry| {
+        let Some(remote_idx) = remote.iter().position(|r| r.frame == local_entry.frame) else {
+            return true;
+        };
+        let remote_entry = remote.remove(remote_idx);
+        if remote_entry.checksum != local_entry.checksum && desync_frame.is_none() {
+            *desync_frame = Some(local_entry.frame);
+            println!(
+                "Desync detected at frame {}!\n  Local  ({:#018x}): {}\n  Remote ({:#018x}): {}",
+                local_entry.frame,
+                local_entry.checksum,
+                local_entry.description,
+                remote_entry.checksum,
+                remote_entry.description
+            );
+        }
+        false
+    });
+
+    // Don't keep unmatched checksums around forever
+    let oldest_kept = confirmed_frame.saturating_sub(CHECKSUM_INTERVAL * CHECKSUM_HISTORY as u32);
+    local.retain(|entry| entry.frame >= oldest_kept);
+    remote.retain(|entry| entry.frame >= oldest_kept);
+}
@@ -0,0 +1,276 @@
+use super::{LocalPlayer, MatchState, ReplayRecorder, SimulationFrame};
+use crate::menu::menu_plugin;
+use crate::networking::{MatchSocket, ReliableMessage};
+use crate::SessionNames;
+use bones_framework::networking::ggrs::{Message, NonBlockingSocket};
+use bones_framework::networking::BoxedNonBlockingSocket;
+use bones_framework::prelude::*;
+use egui::{Color32, RichText};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long the other peer's GGRS messages may be missing before the connection counts as
+/// interrupted, the default `disconnect_notify_start` of GGRS sessions
+const DISCONNECT_NOTIFY_START: Duration = Duration::from_millis(500);
+/// How long the other peer's GGRS messages may be missing before it counts as disconnected, the
+/// default `disconnect_timeout` of GGRS sessions
+const DISCONNECT_TIMEOUT: Duration = Duration::from_millis(2000);
+/// How long the disconnect overlay is shown before returning to the main menu
+const RETURN_TO_MENU_DELAY: Duration = Duration::from_secs(5);
+
+/// The state of the GGRS connection to the other peer, following the `NetworkInterrupted`,
+/// `NetworkResumed` and `Disconnected` events of GGRS sessions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GgrsConnectionState {
+    Connected,
+    /// No message arrived for DISCONNECT_NOTIFY_START, GGRS keeps predicting the other peer
+    Interrupted,
+    /// No message arrived for DISCONNECT_TIMEOUT, GGRS gave up on the other peer
+    Disconnected,
+}
+
+impl GgrsConnectionState {
+    /// Returns the state after the other peer has been silent for the given time
+    pub fn from_silence(silence: Duration) -> Self {
+        if silence >= DISCONNECT_TIMEOUT {
+            GgrsConnectionState::Disconnected
+        } else if silence >= DISCONNECT_NOTIFY_START {
+            GgrsConnectionState::Interrupted
+        } else {
+            GgrsConnectionState::Connected
+        }
+    }
+}
+
+/// Watches the GGRS traffic of an online match.
+///
+/// The GgrsSessionRunner handles the events of its GGRS session itself without passing them on,
+/// so they are derived from the GGRS messages instead: GGRS peers keep sending inputs or
+/// keep-alive messages, and GGRS raises its events when they stop arriving for the same timeouts.
+#[derive(Clone, Default)]
+pub struct GgrsConnection {
+    /// When the last GGRS message of the other peer was received
+    last_received: Arc<Mutex<Option<Instant>>>,
+}
+
+impl GgrsConnection {
+    /// Wraps the GGRS socket of a match so its incoming messages are watched
+    pub fn watch(&self, socket: BoxedNonBlockingSocket) -> BoxedNonBlockingSocket {
+        BoxedNonBlockingSocket(Box::new(WatchedSocket {
+            inner: socket,
+            last_received: self.last_received.clone(),
+        }))
+    }
+
+    /// Returns the state of the connection, the timeouts start with the first check so that the
+    /// time spent before the match started isn't counted
+    pub fn state(&self, now: Instant) -> GgrsConnectionState {
+        let last_received = *self.last_received.lock().unwrap().get_or_insert(now);
+        GgrsConnectionState::from_silence(now.saturating_duration_since(last_received))
+    }
+}
+
+/// A GGRS socket which records when messages of the other peer arrive
+struct WatchedSocket {
+    inner: BoxedNonBlockingSocket,
+    last_received: Arc<Mutex<Option<Instant>>>,
+}
+
+impl NonBlockingSocket<usize> for WatchedSocket {
+    fn send_to(&mut self, msg: &Message, addr: &usize) {
+        self.inner.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(usize, Message)> {
+        let messages = self.inner.receive_all_messages();
+        if !messages.is_empty() {
+            *self.last_received.lock().unwrap() = Some(Instant::now());
+        }
+        messages
+    }
+}
+
+/// Watches the connection to the other peer during an online match, lives in the gameplay_ui
+/// session since it works in real time rather than simulation frames.
+///
+/// When GGRS gives up on the other peer the simulation would stall waiting for its inputs, so the
+/// gameplay session is paused instead and both sessions are torn down after a short countdown.
+/// A peer leaving on purpose says so with a reliable message.
+#[derive(HasSchema, Clone, Default)]
+pub struct ConnectionMonitor {
+    /// The GGRS traffic of the match, None for local matches
+    pub connection: Option<GgrsConnection>,
+    /// The last known state of the GGRS connection
+    pub connection_state: Option<GgrsConnectionState>,
+    /// When the other peer was detected as disconnected
+    pub disconnected_at: Option<Instant>,
+    /// Whether the other peer left on purpose rather than losing the connection
+    pub opponent_left: bool,
+    /// When the local player left the match, the sessions are torn down shortly after so the
+    /// message notifying the other peer gets sent first
+    pub left_at: Option<Instant>,
+}
+
+impl ConnectionMonitor {
+    /// Creates a monitor for the match whose GGRS traffic is watched by the given connection
+    pub fn new(connection: GgrsConnection) -> Self {
+        Self {
+            connection: Some(connection),
+            ..default()
+        }
+    }
+}
+
+/// Detects when the other peer leaves or stops responding
+pub fn monitor_opponent_connection(
+    match_socket: Res<MatchSocket>,
+    mut monitor: ResMut<ConnectionMonitor>,
+    mut sessions: ResMut<Sessions>,
+    mut session_options: ResMut<SessionOptions>,
+) {
+    if match_socket.socket.is_none() {
+        return;
+    }
+    let now = Instant::now();
+    if monitor.left_at.is_some() {
+        return;
+    }
+
+    if let Some(disconnected_at) = monitor.disconnected_at {
+        if now.duration_since(disconnected_at) >= RETURN_TO_MENU_DELAY {
+            return_to_main_menu(&mut sessions, &mut session_options);
+        }
+        return;
+    }
+
+    let opponent_left = match_socket
+        .messages
+        .iter()
+        .any(|message| matches!(message, ReliableMessage::LeftMatch));
+    if opponent_left {
+        println!("Opponent left the match");
+        monitor.disconnected_at = Some(now);
+        monitor.opponent_left = true;
+        handle_opponent_disconnect(&mut sessions);
+        return;
+    }
+
+    let Some(state) = monitor
+        .connection
+        .as_ref()
+        .map(|connection| connection.state(now))
+    else {
+        return;
+    };
+    if monitor.connection_state != Some(state) {
+        match state {
+            GgrsConnectionState::Connected if monitor.connection_state.is_some() => {
+                println!("Connection to the opponent resumed")
+            }
+            GgrsConnectionState::Connected => {}
+            GgrsConnectionState::Interrupted => println!("Connection to the opponent interrupted"),
+            GgrsConnectionState::Disconnected => {
+                println!("Opponent disconnected");
+                monitor.disconnected_at = Some(now);
+                handle_opponent_disconnect(&mut sessions);
+            }
+        }
+        monitor.connection_state = Some(state);
+    }
+}
+
+/// Pauses the gameplay session and records the match as a forfeit win if the opponent was losing.
+/// The confirmed part of the match is saved as a replay along with the forfeit, so the result
+/// outlives the session.
+fn handle_opponent_disconnect(sessions: &mut Sessions) {
+    let Some(session) = sessions.get_mut(SessionNames::GAMEPLAY) else {
+        return;
+    };
+
+    // Nothing will be simulated anymore, so the result can't be rolled back
+    session.active = false;
+    let local_player_idx = session.world.resource::<LocalPlayer>().idx as usize;
+    let forfeit_winner = {
+        let mut match_state = session.world.resource_mut::<MatchState>();
+        if !match_state.is_finished() && match_state.is_leading(local_player_idx) {
+            match_state.forfeit(local_player_idx);
+        }
+        match_state.forfeit_winner()
+    };
+
+    let confirmed_frame = session
+        .world
+        .resource::<SimulationFrame>()
+        .confirmed_frame();
+    if let Some(replay_recorder) = session.world.get_resource::<ReplayRecorder>() {
+        replay_recorder.save_unfinished(confirmed_frame, forfeit_winner);
+    }
+}
+
+/// Deletes the gameplay and gameplay_ui sessions and goes back to the main menu
+pub fn return_to_main_menu(sessions: &mut Sessions, session_options: &mut SessionOptions) {
+    sessions.delete(SessionNames::GAMEPLAY);
+    session_options.delete = true;
+    sessions
+        .create(SessionNames::MAIN_MENU)
+        .install_plugin(menu_plugin);
+}
+
+/// Draws the disconnect overlay with the countdown until returning to the main menu, or a notice
+/// while the connection is interrupted
+pub fn draw_disconnect_overlay(
+    sessions: Res<Sessions>,
+    monitor: Res<ConnectionMonitor>,
+    ctx: Res<EguiCtx>,
+) {
+    let Some(disconnected_at) = monitor.disconnected_at else {
+        if monitor.connection_state == Some(GgrsConnectionState::Interrupted) {
+            egui::Area::new("connection_interrupted")
+                .anchor(egui::Align2::CENTER_TOP, (0.0, 20.0))
+                .show(&ctx, |ui| {
+                    let text = RichText::new("Connection interrupted, waiting for the opponent...")
+                        .color(Color32::YELLOW)
+                        .size(24.0);
+                    ui.label(text);
+                });
+        }
+        return;
+    };
+    let forfeit_win = sessions
+        .get(SessionNames::GAMEPLAY)
+        .and_then(|session| session.world.get_resource::<MatchState>())
+        .map_or(false, |match_state| match_state.forfeit_winner().is_some());
+    let seconds_left = RETURN_TO_MENU_DELAY
+        .saturating_sub(disconnected_at.elapsed())
+        .as_secs_f32()
+        .ceil();
+
+    egui::CentralPanel::default()
+        .frame(egui::Frame::none().fill(Color32::from_black_alpha(180)))
+        .show(&ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(200.0);
+                let title = if monitor.opponent_left {
+                    "Opponent left the match"
+                } else {
+                    "Opponent disconnected"
+                };
+                let text = RichText::new(title).color(Color32::RED).size(50.0).strong();
+                ui.label(text);
+                if forfeit_win {
+                    let text = RichText::new("You win by forfeit!")
+                        .color(Color32::YELLOW)
+                        .size(36.0);
+                    ui.label(text);
+                    let text = RichText::new("The result was saved with the replay")
+                        .color(Color32::WHITE)
+                        .size(20.0);
+                    ui.label(text);
+                }
+                let text = RichText::new(format!("Returning to menu in {}", seconds_left))
+                    .color(Color32::WHITE)
+                    .size(28.0);
+                ui.label(text);
+            });
+        });
+}
@@ -0,0 +1,279 @@
+use bones_framework::prelude::*;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// The number of fractional bits of a Fixed value
+const FRAC_BITS: u32 = 16;
+/// The raw representation of 1.0
+const ONE_BITS: i64 = 1 << FRAC_BITS;
+
+/// A deterministic fixed-point number with 16 fractional bits.
+///
+/// All arithmetic is done on integers, so the simulation produces bit-identical results on every
+/// CPU, OS and compiler, which GGRS rollback between different platforms relies on.
+#[derive(HasSchema, Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(C)]
+pub struct Fixed {
+    bits: i64,
+}
+
+impl Fixed {
+    pub const ZERO: Fixed = Fixed { bits: 0 };
+    pub const ONE: Fixed = Fixed { bits: ONE_BITS };
+    /// PI rounded to the nearest representable value
+    pub const PI: Fixed = Fixed { bits: 205_887 };
+    pub const FRAC_PI_2: Fixed = Fixed { bits: 102_944 };
+    pub const FRAC_PI_4: Fixed = Fixed { bits: 51_472 };
+
+    /// Creates a Fixed from its raw bits
+    pub const fn from_bits(bits: i64) -> Self {
+        Self { bits }
+    }
+
+    /// Returns the raw bits of the value
+    pub const fn to_bits(self) -> i64 {
+        self.bits
+    }
+
+    /// Creates a Fixed from an integer
+    pub const fn from_int(value: i32) -> Self {
+        Self {
+            bits: (value as i64) << FRAC_BITS,
+        }
+    }
+
+    /// Creates a Fixed from a float, rounding to the nearest representable value.
+    ///
+    /// Scaling by a power of two and rounding are exact IEEE operations, so this conversion is
+    /// deterministic and safe to use on config values.
+    pub fn from_f32(value: f32) -> Self {
+        Self {
+            bits: (value as f64 * ONE_BITS as f64).round() as i64,
+        }
+    }
+
+    /// Converts the value to a float, only meant for rendering and debugging
+    pub fn to_f32(self) -> f32 {
+        (self.bits as f64 / ONE_BITS as f64) as f32
+    }
+
+    /// Returns the absolute value
+    pub fn abs(self) -> Self {
+        Self {
+            bits: self.bits.abs(),
+        }
+    }
+
+    /// Returns -1, 0 or 1 depending on the sign of the value
+    pub fn signum(self) -> Self {
+        Self::from_int(self.bits.signum() as i32)
+    }
+
+    /// Returns the square root, or zero for negative values
+    pub fn sqrt(self) -> Self {
+        if self.bits <= 0 {
+            return Self::ZERO;
+        }
+        Self {
+            bits: isqrt((self.bits as u128) << FRAC_BITS) as i64,
+        }
+    }
+
+    /// Returns the sine of the value (in radians)
+    pub fn sin(self) -> Self {
+        // Reduce the angle to -PI..=PI
+        let two_pi = Self::PI + Self::PI;
+        let mut x = Self {
+            bits: self.bits.rem_euclid(two_pi.bits),
+        };
+        if x > Self::PI {
+            x -= two_pi;
+        }
+
+        // Use the symmetry around PI/2 to keep the series in its most accurate range
+        if x > Self::FRAC_PI_2 {
+            x = Self::PI - x;
+        } else if x < -Self::FRAC_PI_2 {
+            x = -Self::PI - x;
+        }
+
+        // Taylor series: x - x^3/3! + x^5/5! - x^7/7!
+        let x2 = x * x;
+        let mut term = x;
+        let mut result = x;
+        for divisor in [6, 20, 42] {
+            term = -(term * x2) / Self::from_int(divisor);
+            result += term;
+        }
+        result
+    }
+
+    /// Returns the cosine of the value (in radians)
+    pub fn cos(self) -> Self {
+        (self + Self::FRAC_PI_2).sin()
+    }
+
+    /// Divides by the given value, returns None if it is zero
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        if rhs.bits == 0 {
+            None
+        } else {
+            Some(self / rhs)
+        }
+    }
+}
+
+/// Integer square root, rounded down
+pub(crate) fn isqrt(value: u128) -> u128 {
+    let mut remainder = value;
+    let mut result = 0;
+    let mut bit = 1u128 << 126;
+    while bit > remainder {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if remainder >= result + bit {
+            remainder -= result + bit;
+            result = (result >> 1) + bit;
+        } else {
+            result >>= 1;
+        }
+        bit >>= 2;
+    }
+    result
+}
+
+impl Add for Fixed {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            bits: self.bits + rhs.bits,
+        }
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            bits: self.bits - rhs.bits,
+        }
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self {
+            bits: ((self.bits as i128 * rhs.bits as i128) >> FRAC_BITS) as i64,
+        }
+    }
+}
+
+/// Panics if the divisor is zero, use `checked_div` for divisors which come from the config
+impl Div for Fixed {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        Self {
+            bits: (((self.bits as i128) << FRAC_BITS) / rhs.bits as i128) as i64,
+        }
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self { bits: -self.bits }
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl MulAssign for Fixed {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+/// A 2D vector of Fixed values
+#[derive(HasSchema, Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FixedVec2 {
+    pub x: Fixed,
+    pub y: Fixed,
+}
+
+impl FixedVec2 {
+    pub const ZERO: FixedVec2 = FixedVec2 {
+        x: Fixed::ZERO,
+        y: Fixed::ZERO,
+    };
+
+    /// Creates a new FixedVec2
+    pub const fn new(x: Fixed, y: Fixed) -> Self {
+        Self { x, y }
+    }
+
+    /// Creates a FixedVec2 from float coordinates
+    pub fn from_f32(x: f32, y: f32) -> Self {
+        Self::new(Fixed::from_f32(x), Fixed::from_f32(y))
+    }
+
+    /// Converts the vector to a Vec2, only meant for rendering and debugging
+    pub fn to_vec2(self) -> Vec2 {
+        Vec2::new(self.x.to_f32(), self.y.to_f32())
+    }
+
+    /// Returns the length of the vector
+    pub fn length(self) -> Fixed {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+
+    /// Returns the vector scaled down to the given length if it is longer, unchanged if `max`
+    /// isn't positive
+    pub fn clamp_length_max(self, max: Fixed) -> Self {
+        let length = self.length();
+        // A positive max also rules out dividing by a zero length
+        if max > Fixed::ZERO && length > max {
+            self * (max / length)
+        } else {
+            self
+        }
+    }
+}
+
+impl Add for FixedVec2 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl Sub for FixedVec2 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl Mul<Fixed> for FixedVec2 {
+    type Output = Self;
+    fn mul(self, rhs: Fixed) -> Self {
+        Self::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+impl AddAssign for FixedVec2 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
@@ -0,0 +1,70 @@
+use super::{LocalPlayer, SimulationFrame};
+use crate::cli::CliArgs;
+use crate::input::MatchInputs;
+use bones_framework::input::PlayerControls;
+use bones_framework::networking::input::NetworkPlayerControl;
+use bones_framework::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Resource holding the file the match inputs are logged to, if inputs logging is enabled.
+///
+/// The writer is shared between clones so that rollback snapshots keep writing to the same file.
+#[derive(HasSchema, Clone, Default)]
+pub struct InputsLogger {
+    writer: Option<Arc<Mutex<BufWriter<File>>>>,
+}
+
+/// Opens the inputs log file when the `--inputs-logging` flag was passed
+pub fn inputs_logging_startup(
+    cli_args: Res<CliArgs>,
+    local_player: Res<LocalPlayer>,
+    mut inputs_logger: ResMut<InputsLogger>,
+) {
+    let Some(path) = &cli_args.inputs_logging else {
+        return;
+    };
+    let path = if path.as_os_str().is_empty() {
+        PathBuf::from("logs").join(format!("inputs_player{}.log", local_player.idx))
+    } else {
+        path.clone()
+    };
+
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    match File::create(&path) {
+        Ok(file) => {
+            println!("Logging match inputs to {}", path.display());
+            inputs_logger.writer = Some(Arc::new(Mutex::new(BufWriter::new(file))));
+        }
+        Err(err) => eprintln!("Failed to create inputs log {}: {}", path.display(), err),
+    }
+}
+
+/// Writes the dense inputs of both players for the current frame.
+///
+/// Frames re-simulated after a rollback are logged again, so the last entry for a frame is the
+/// confirmed one.
+pub fn log_match_inputs(
+    inputs_logger: Res<InputsLogger>,
+    match_inputs: Res<MatchInputs>,
+    simulation_frame: Res<SimulationFrame>,
+) {
+    let Some(writer) = &inputs_logger.writer else {
+        return;
+    };
+    let mut writer = writer.lock().unwrap();
+    let player_0 = match_inputs.get_control(0).get_dense_input();
+    let player_1 = match_inputs.get_control(1).get_dense_input();
+    let _ = writeln!(
+        writer,
+        "{} {:#010x} {:#010x}",
+        simulation_frame.frame,
+        player_0.bits(),
+        player_1.bits()
+    );
+    let _ = writer.flush();
+}
@@ -0,0 +1,137 @@
+use super::{Ball, MatchPhase, MatchRules, MatchState, Player, SimulationFrame};
+use crate::SessionNames;
+use bones_framework::prelude::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// The positions of the players and the ball in a simulated frame of a rally
+#[derive(Clone, Copy, Debug)]
+struct RallyFrame {
+    frame: u32,
+    /// The player positions by player index
+    players: [Vec2; 2],
+    ball: Vec2,
+    /// Whether a point was scored in this frame, which ends the rally
+    point_scored: bool,
+}
+
+/// Rolling buffer of the last frames of the current rally, shown again as an instant replay.
+///
+/// Render-only, so it is shared between clones instead of being rolled back. Frames re-simulated
+/// after a rollback replace the ones recorded while they were predicted.
+#[derive(HasSchema, Clone, Default)]
+pub struct RallyHistory {
+    frames: Arc<Mutex<VecDeque<RallyFrame>>>,
+}
+
+impl RallyHistory {
+    /// Returns the player positions by player index and the ball position shown in the instant
+    /// replay with the given frames left, None if no rally was recorded
+    pub fn replay_positions(
+        &self,
+        rules: &MatchRules,
+        frames_left: u32,
+    ) -> Option<([Vec2; 2], Vec2)> {
+        let frames = self.frames.lock().unwrap();
+        let elapsed = rules.instant_replay_frames().saturating_sub(frames_left);
+        let entry = frames
+            .get((elapsed / rules.slow_motion_factor.max(1)) as usize)
+            .or(frames.back())?;
+        Some((entry.players, entry.ball))
+    }
+}
+
+/// Records the positions of the players and the ball during rallies, up to the frame in which
+/// the point was scored. Runs after the transforms are synced.
+pub fn record_rally_history(
+    entities: Res<Entities>,
+    players: Comp<Player>,
+    balls: Comp<Ball>,
+    match_phase: Res<MatchPhase>,
+    match_state: Res<MatchState>,
+    simulation_frame: Res<SimulationFrame>,
+    rally_history: Res<RallyHistory>,
+) {
+    let frame = simulation_frame.frame;
+    let mut frames = rally_history.frames.lock().unwrap();
+    while frames.back().is_some_and(|entry| entry.frame >= frame) {
+        frames.pop_back();
+    }
+
+    let point_scored = match *match_phase {
+        MatchPhase::Rally => false,
+        MatchPhase::PointScored { .. } | MatchPhase::MatchOver { .. } => true,
+        _ => return,
+    };
+    let last_point_scored = frames.back().map(|entry| entry.point_scored);
+    if point_scored && last_point_scored != Some(false) {
+        // Nothing moves after the frame the point was scored in
+        return;
+    }
+    if !point_scored && last_point_scored == Some(true) {
+        // A new rally started
+        frames.clear();
+    }
+
+    let mut entry = RallyFrame {
+        frame,
+        players: [Vec2::ZERO; 2],
+        ball: Vec2::ZERO,
+        point_scored,
+    };
+    for (_ent, player) in entities.iter_with(&players) {
+        entry.players[player.idx] = player.position.to_vec2();
+    }
+    if let Some((_ent, ball)) = entities.iter_with(&balls).next() {
+        entry.ball = ball.position.to_vec2();
+    }
+    frames.push_back(entry);
+    while frames.len() > match_state.rules().rally_replay_frames as usize {
+        frames.pop_front();
+    }
+}
+
+/// Moves the sprites of the players and the ball along the recorded rally in slow motion during
+/// the instant replay, holding the last frame once the rally is over.
+///
+/// Runs in the gameplay_ui session after the gameplay session was stepped, so only what is
+/// rendered changes: the simulation never reads the transforms before syncing them from the
+/// simulated positions again.
+pub fn show_instant_replay(sessions: Res<Sessions>) {
+    let Some(session) = sessions.get(SessionNames::GAMEPLAY) else {
+        return;
+    };
+    let positions = {
+        let (Some(match_phase), Some(match_state), Some(rally_history)) = (
+            session.world.get_resource::<MatchPhase>(),
+            session.world.get_resource::<MatchState>(),
+            session.world.get_resource::<RallyHistory>(),
+        ) else {
+            return;
+        };
+        let MatchPhase::InstantReplay { frames_left } = *match_phase else {
+            return;
+        };
+        rally_history.replay_positions(match_state.rules(), frames_left)
+    };
+    let Some((player_positions, ball_position)) = positions else {
+        return;
+    };
+
+    session.world.run_system(
+        |entities: Res<Entities>,
+         players: Comp<Player>,
+         balls: Comp<Ball>,
+         mut transforms: CompMut<Transform>| {
+            for (_ent, (player, transform)) in entities.iter_with((&players, &mut transforms)) {
+                transform.translation.x = player_positions[player.idx].x;
+                transform.translation.y = player_positions[player.idx].y;
+            }
+            for (_ent, (_ball, transform)) in entities.iter_with((&balls, &mut transforms)) {
+                transform.translation.x = ball_position.x;
+                transform.translation.y = ball_position.y;
+            }
+        },
+        (),
+    );
+}
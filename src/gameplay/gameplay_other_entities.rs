@@ -1,11 +1,13 @@
-use super::{gameplay::*, MatchState};
+use super::{gameplay::*, Fixed, FixedVec2, MatchPhase, MatchState, Player};
 use bones_framework::prelude::*;
 
 /// Represents the ball in the game
 #[derive(HasSchema, Default, Clone)]
 #[repr(C)]
 pub struct Ball {
-    pub velocity: Vec2,
+    /// The simulated position of the ball, the Transform is only synced from it for rendering
+    pub position: FixedVec2,
+    pub velocity: FixedVec2,
 }
 
 /// Represents the floor in the game
@@ -42,60 +44,69 @@ pub fn create_circle_path(radius: f32, color: Color) -> Path2d {
 pub fn ball_movement(
     entities: Res<Entities>,
     mut balls: CompMut<Ball>,
-    mut transforms: CompMut<Transform>,
     mut match_state: ResMut<MatchState>,
+    mut match_phase: ResMut<MatchPhase>,
+    physics: Res<PhysicsMeta>,
 ) {
-    if match_state.is_finished() {
+    // The ball only moves while it is in play
+    if !match_phase.is_rally() {
         return;
     }
 
-    for (_ent, (ball, transform)) in entities.iter_with((&mut balls, &mut transforms)) {
+    let physics = physics.to_fixed();
+    for (_ent, ball) in entities.iter_with(&mut balls) {
         // Apply gravity
-        ball.velocity.y -= GRAVITY;
+        ball.velocity.y -= physics.gravity;
 
         // Update position
-        transform.translation.x += ball.velocity.x;
-        transform.translation.y += ball.velocity.y;
+        ball.position += ball.velocity;
 
         // Handle horizontal boundary collisions
-        if transform.translation.x - BALL_RADIUS <= LEFT_BOUNDARY
-            || transform.translation.x + BALL_RADIUS >= RIGHT_BOUNDARY
+        if ball.position.x - physics.ball_radius <= physics.left_boundary
+            || ball.position.x + physics.ball_radius >= physics.right_boundary
         {
-            ball.velocity.x = -ball.velocity.x * BALL_BOUNCE_FACTOR;
-            transform.translation.x = transform
-                .translation
-                .x
-                .clamp(LEFT_BOUNDARY + BALL_RADIUS, RIGHT_BOUNDARY - BALL_RADIUS);
+            ball.velocity.x = -ball.velocity.x * physics.ball_bounce_factor;
+            ball.position.x = ball.position.x.clamp(
+                physics.left_boundary + physics.ball_radius,
+                physics.right_boundary - physics.ball_radius,
+            );
         }
 
         // Handle ceiling collision
-        if transform.translation.y + BALL_RADIUS >= 290.0 {
-            ball.velocity.y = -ball.velocity.y * BALL_BOUNCE_FACTOR;
-            transform.translation.y = 290.0 - BALL_RADIUS;
+        if ball.position.y + physics.ball_radius >= physics.ceiling_level {
+            ball.velocity.y = -ball.velocity.y * physics.ball_bounce_factor;
+            ball.position.y = physics.ceiling_level - physics.ball_radius;
         }
 
         // Handle floor collision and scoring
-        if transform.translation.y + BALL_RADIUS <= GROUND_LEVEL {
-            let reset_to_right = transform.translation.x > CENTER_BOUNDARY;
-            let scoring_player = if reset_to_right { 0 } else { 1 };
-            match_state.increment_player_score(scoring_player);
-            ball.reset(reset_to_right, transform);
+        if ball.position.y + physics.ball_radius <= physics.ground_level {
+            // The player on the side the ball landed loses the point
+            let landing_side = if ball.position.x > physics.center_boundary {
+                1
+            } else {
+                0
+            };
+            let losing_player = match_state.player_on_side(landing_side);
+            match_state.award_point(1 - losing_player);
+            match_phase.on_point_scored(&match_state, 1 - losing_player);
+            ball.velocity = FixedVec2::ZERO;
         }
 
         // Clamp ball speed
-        let speed = ball.velocity.length();
-        if speed > MAX_BALL_SPEED {
-            ball.velocity = ball.velocity.normalize() * MAX_BALL_SPEED;
-        }
+        ball.velocity = ball.velocity.clamp_length_max(physics.max_ball_speed);
     }
 }
 
 impl Ball {
-    /// Resets the ball's position and velocity
-    pub fn reset(&mut self, reset_to_right: bool, transform: &mut Transform) {
-        transform.translation.x = if reset_to_right { 290.0 } else { -290.0 };
-        transform.translation.y = 0.0;
-        self.velocity = Vec2::new(0.0, GRAVITY * 30.0);
+    /// Holds the ball still above the given x position, at the height it is served from
+    pub fn hold_at(&mut self, x: Fixed) {
+        self.position = FixedVec2::new(x, Fixed::ZERO);
+        self.velocity = FixedVec2::ZERO;
+    }
+
+    /// Tosses the ball up from where it is held
+    pub fn serve(&mut self, physics: &FixedPhysics) {
+        self.velocity = FixedVec2::new(Fixed::ZERO, physics.gravity * Fixed::from_int(30));
     }
 }
 
@@ -103,66 +114,88 @@ impl Ball {
 pub fn ball_net_collision(
     entities: Res<Entities>,
     mut balls: CompMut<Ball>,
-    mut transforms: CompMut<Transform>,
-    nets: Comp<Net>,
-    match_state: Res<MatchState>,
+    match_phase: Res<MatchPhase>,
+    physics: Res<PhysicsMeta>,
 ) {
-    if match_state.is_finished() {
+    if !match_phase.is_rally() {
         return;
     }
 
-    let mut ball_updates = Vec::new();
-    // Find the net position (assuming there's only one net)
-    let mut net_position = Vec3::ZERO;
-    for (_net_ent, (_net, net_transform)) in entities.iter_with((&nets, &transforms)) {
-        net_position = net_transform.translation;
-        break; // We only need one net
-    }
-
-    for (ball_ent, (ball, ball_transform)) in entities.iter_with((&balls, &transforms)) {
-        let ball_center = Vec2::new(ball_transform.translation.x, ball_transform.translation.y);
+    let physics = physics.to_fixed();
+    let half_net_width = physics.net_width / Fixed::from_int(2);
+    let net_position = FixedVec2::new(physics.center_boundary, physics.net_base_level);
 
+    for (_ball_ent, ball) in entities.iter_with(&mut balls) {
         // Check for collision with the net
-        if (ball_center.x - net_position.x).abs() < NET_WIDTH / 2.0 + BALL_RADIUS
-            && ball_center.y > net_position.y
-            && ball_center.y < net_position.y + NET_HEIGHT + BALL_RADIUS
+        if (ball.position.x - net_position.x).abs() < half_net_width + physics.ball_radius
+            && ball.position.y > net_position.y
+            && ball.position.y < net_position.y + physics.net_height + physics.ball_radius
         {
-            let mut new_velocity = ball.velocity;
-            let mut new_position = ball_center;
-
             // Check if it's a top collision
-            if ball_center.y >= net_position.y + NET_HEIGHT - BALL_RADIUS {
+            if ball.position.y >= net_position.y + physics.net_height - physics.ball_radius {
                 // Top collision: Reverse y velocity and maintain x velocity
-                new_velocity.y = -new_velocity.y * BALL_BOUNCE_FACTOR;
-                new_position.y = net_position.y + NET_HEIGHT + BALL_RADIUS;
+                ball.velocity.y = -ball.velocity.y * physics.ball_bounce_factor;
+                ball.position.y = net_position.y + physics.net_height + physics.ball_radius;
             } else {
                 // Side collision: Reverse x velocity
-                new_velocity.x = -new_velocity.x * BALL_BOUNCE_FACTOR;
+                ball.velocity.x = -ball.velocity.x * physics.ball_bounce_factor;
 
                 // Adjust x position to prevent sticking
-                new_position.x = if ball_center.x < net_position.x {
-                    net_position.x - NET_WIDTH / 2.0 - BALL_RADIUS - 1.0
+                let offset = half_net_width + physics.ball_radius + Fixed::ONE;
+                ball.position.x = if ball.position.x < net_position.x {
+                    net_position.x - offset
                 } else {
-                    net_position.x + NET_WIDTH / 2.0 + BALL_RADIUS + 1.0
+                    net_position.x + offset
                 };
             }
-
-            ball_updates.push((ball_ent, new_velocity, new_position));
         }
     }
+}
 
-    // Apply updates
-    for (ball_ent, new_velocity, new_position) in ball_updates {
-        if let (Some(ball), Some(ball_transform)) =
-            (balls.get_mut(ball_ent), transforms.get_mut(ball_ent))
-        {
-            ball.velocity = new_velocity;
-            ball_transform.translation.x = new_position.x;
-            ball_transform.translation.y = new_position.y;
+/// Resets the touch count once the ball is on the other side of the net than the last touch
+pub fn reset_touches_on_net_cross(
+    entities: Res<Entities>,
+    balls: Comp<Ball>,
+    mut match_state: ResMut<MatchState>,
+    physics: Res<PhysicsMeta>,
+) {
+    let Some(last_touch_player) = match_state.last_touch_player() else {
+        return;
+    };
+
+    let physics = physics.to_fixed();
+    for (_ent, ball) in entities.iter_with(&balls) {
+        let ball_side = if ball.position.x > physics.center_boundary {
+            1
+        } else {
+            0
+        };
+        if ball_side != match_state.side_of(last_touch_player) {
+            match_state.reset_touches();
         }
     }
 }
 
+/// Syncs the Transforms of players and balls from their simulated positions, only used for
+/// rendering so the simulation itself never reads float positions.
+pub fn sync_transforms(
+    entities: Res<Entities>,
+    players: Comp<Player>,
+    balls: Comp<Ball>,
+    mut transforms: CompMut<Transform>,
+) {
+    for (_ent, (player, transform)) in entities.iter_with((&players, &mut transforms)) {
+        let position = player.position.to_vec2();
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+    for (_ent, (ball, transform)) in entities.iter_with((&balls, &mut transforms)) {
+        let position = ball.position.to_vec2();
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
 /// Updates the visibility of the ball based on the match state, used for hiding the ball when match finished.
 pub fn update_ball_visibility(
     entities: Res<Entities>,
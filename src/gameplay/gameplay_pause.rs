@@ -0,0 +1,233 @@
+use super::{
+    return_to_main_menu, ConnectionMonitor, LocalPlayer, MatchPhase, MatchState, SimulationFrame,
+};
+use crate::input::MatchInputs;
+use crate::networking::{MatchSocket, ReliableMessage, FPS};
+use crate::replay::ReplayStatus;
+use crate::spectator::SpectatorStatus;
+use crate::SessionNames;
+use bones_framework::input::PlayerControls;
+use bones_framework::networking::SocketTarget;
+use bones_framework::prelude::*;
+use egui::{Color32, RichText};
+use std::time::Duration;
+
+/// How long the sessions are kept around after sending the leave message to the other peer
+const LEAVE_DELAY: Duration = Duration::from_millis(300);
+
+/// The state of the pause menu. It is driven by the match inputs, so both peers of an online
+/// match pause together and the state is rolled back with the rest of the world.
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct PauseState {
+    /// The player who opened the pause menu, only they can navigate it
+    pub paused_by: Option<usize>,
+    /// The phase restored when the game is resumed
+    resume_phase: MatchPhase,
+    /// Whether "Yes" is selected in the "Leave match?" dialog
+    pub leave_selected: bool,
+    /// The simulation frame at which the pausing player confirmed leaving the match
+    pub leave_confirmed_at: Option<u32>,
+    /// The frames until the match resumes by itself, None if the pause has no time limit
+    pub frames_left: Option<u32>,
+}
+
+impl PauseState {
+    /// Opens the pause menu for the specified player and freezes the match for at most
+    /// `timeout_frames`, 0 for no limit
+    pub fn pause(&mut self, player_idx: usize, match_phase: &mut MatchPhase, timeout_frames: u32) {
+        self.paused_by = Some(player_idx);
+        self.resume_phase = *match_phase;
+        self.leave_selected = false;
+        self.frames_left = (timeout_frames > 0).then_some(timeout_frames);
+        *match_phase = MatchPhase::Paused;
+    }
+
+    /// Closes the pause menu and restores the phase the match was paused in
+    pub fn resume(&mut self, match_phase: &mut MatchPhase) {
+        self.paused_by = None;
+        self.frames_left = None;
+        *match_phase = self.resume_phase;
+    }
+}
+
+/// Opens the pause menu when a player presses escape/start and lets them answer the
+/// "Leave match?" dialog: left/right to select, jump/enter to confirm, escape/start to resume.
+/// The match resumes by itself once the pause timeout of the rules runs out, so the other player
+/// isn't stuck waiting.
+pub fn handle_pause_input(
+    match_inputs: Res<MatchInputs>,
+    match_state: Res<MatchState>,
+    simulation_frame: Res<SimulationFrame>,
+    mut match_phase: ResMut<MatchPhase>,
+    mut pause_state: ResMut<PauseState>,
+) {
+    if pause_state.leave_confirmed_at.is_some() {
+        return;
+    }
+
+    let Some(paused_by) = pause_state.paused_by else {
+        if let Some(player_idx) =
+            (0..2).find(|&player_idx| match_inputs.get_control(player_idx).esc_start_just_pressed)
+        {
+            let timeout_frames = match_state.rules().pause_timeout_frames;
+            pause_state.pause(player_idx, &mut match_phase, timeout_frames);
+        }
+        return;
+    };
+
+    if let Some(frames_left) = pause_state.frames_left {
+        if frames_left <= 1 {
+            pause_state.resume(&mut match_phase);
+            return;
+        }
+        pause_state.frames_left = Some(frames_left - 1);
+    }
+
+    let player_control = match_inputs.get_control(paused_by);
+    if player_control.esc_start_just_pressed {
+        pause_state.resume(&mut match_phase);
+    } else if player_control.left_pressed {
+        pause_state.leave_selected = true;
+    } else if player_control.right_pressed {
+        pause_state.leave_selected = false;
+    } else if player_control.jump_just_pressed || player_control.enter_just_pressed {
+        if pause_state.leave_selected {
+            pause_state.leave_confirmed_at = Some(simulation_frame.frame);
+        } else {
+            pause_state.resume(&mut match_phase);
+        }
+    }
+}
+
+/// Leaves the match once the local player confirmed it in the pause menu. Online, the other peer
+/// is notified first and then both the gameplay and gameplay_ui sessions are torn down.
+///
+/// Online, the decision is only acted upon once the frame it was made on is confirmed, since a
+/// rollback may still change who paused; the other peer's leave message is handled by the
+/// ConnectionMonitor. Replays and spectated matches have no socket either, they keep showing the
+/// recorded leave instead of returning to the menu.
+pub fn handle_leave_match(
+    mut sessions: ResMut<Sessions>,
+    mut session_options: ResMut<SessionOptions>,
+    match_socket: Res<MatchSocket>,
+    mut monitor: ResMut<ConnectionMonitor>,
+) {
+    if let Some(left_at) = monitor.left_at {
+        if left_at.elapsed() >= LEAVE_DELAY {
+            return_to_main_menu(&mut sessions, &mut session_options);
+        }
+        return;
+    }
+
+    let Some(session) = sessions.get_mut(SessionNames::GAMEPLAY) else {
+        return;
+    };
+    let (Some(pause_state), Some(local_player), Some(simulation_frame)) = (
+        session.world.get_resource::<PauseState>(),
+        session.world.get_resource::<LocalPlayer>(),
+        session.world.get_resource::<SimulationFrame>(),
+    ) else {
+        return;
+    };
+    let (Some(paused_by), Some(leave_confirmed_at)) =
+        (pause_state.paused_by, pause_state.leave_confirmed_at)
+    else {
+        return;
+    };
+    let is_playback = session.world.get_resource::<ReplayStatus>().is_some()
+        || session.world.get_resource::<SpectatorStatus>().is_some();
+
+    match &match_socket.socket {
+        None if is_playback => {}
+        None => return_to_main_menu(&mut sessions, &mut session_options),
+        // GGRS may still roll back to the frame the decision was made on
+        Some(_) if simulation_frame.confirmed_frame() < leave_confirmed_at => {}
+        Some(socket) if paused_by == local_player.idx as usize => {
+            socket.send_reliable(SocketTarget::All, &ReliableMessage::LeftMatch.to_bytes());
+            monitor.left_at = Some(Instant::now());
+            session.active = false;
+        }
+        Some(_) => {}
+    }
+}
+
+/// Draws the pause menu. The player who paused gets the "Leave match?" dialog, in online matches
+/// the other player and spectators are told that the match is paused.
+pub fn draw_pause_overlay(
+    sessions: Res<Sessions>,
+    match_socket: Res<MatchSocket>,
+    ctx: Res<EguiCtx>,
+) {
+    let Some(session) = sessions.get(SessionNames::GAMEPLAY) else {
+        return;
+    };
+    let Some(pause_state) = session.world.get_resource::<PauseState>() else {
+        return;
+    };
+    let Some(paused_by) = pause_state.paused_by else {
+        return;
+    };
+    // Spectators have no local player and never get the dialog
+    let local_player = session.world.get_resource::<LocalPlayer>();
+    let is_online = match_socket.socket.is_some();
+    let show_dialog = local_player
+        .as_ref()
+        .is_some_and(|local_player| !is_online || paused_by == local_player.idx as usize);
+    let timeout_text = pause_state.frames_left.map(|frames_left| {
+        let seconds_left = (frames_left as f32 / FPS).ceil();
+        RichText::new(format!("Resuming in {}", seconds_left))
+            .color(Color32::LIGHT_GRAY)
+            .size(20.0)
+    });
+
+    egui::CentralPanel::default()
+        .frame(egui::Frame::none().fill(Color32::from_black_alpha(160)))
+        .show(&ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(200.0);
+                ui.label(
+                    RichText::new("Paused")
+                        .color(Color32::WHITE)
+                        .size(60.0)
+                        .strong(),
+                );
+                if !show_dialog {
+                    let message = if local_player.is_some() {
+                        "The opponent paused the match"
+                    } else {
+                        "A player paused the match"
+                    };
+                    let text = RichText::new(message).color(Color32::WHITE).size(28.0);
+                    ui.label(text);
+                    if let Some(text) = timeout_text {
+                        ui.label(text);
+                    }
+                    return;
+                }
+
+                ui.label(
+                    RichText::new("Leave match?")
+                        .color(Color32::WHITE)
+                        .size(36.0),
+                );
+                ui.add_space(10.0);
+                let (yes, no) = if pause_state.leave_selected {
+                    ("[ Yes ]", "  No  ")
+                } else {
+                    ("  Yes  ", "[ No ]")
+                };
+                let text = RichText::new(format!("{}    {}", yes, no))
+                    .color(Color32::YELLOW)
+                    .size(32.0);
+                ui.label(text);
+                ui.add_space(10.0);
+                let text = RichText::new("Escape: Resume")
+                    .color(Color32::WHITE)
+                    .size(20.0);
+                ui.label(text);
+                if let Some(text) = timeout_text {
+                    ui.label(text);
+                }
+            });
+        });
+}
@@ -0,0 +1,182 @@
+use super::{gameplay::*, Ball, BotPlayers, MatchState, Player};
+use crate::input::MatchInputs;
+use bones_framework::input::PlayerControls;
+use bones_framework::prelude::*;
+
+/// The phase of the match, a resource of the gameplay session so it is rolled back along with
+/// the rest of the world. Every simulation system checks it to know whether it should run.
+#[derive(HasSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchPhase {
+    /// The countdown before the first serve, nothing moves
+    PreMatch { frames_left: u32 },
+    /// The server holds the ball above their sprite until they jump or the serve timer runs out
+    Serving { frames_left: u32 },
+    /// The ball is in play
+    Rally,
+    /// Play is frozen for a moment after a point, showing who scored
+    PointScored { frames_left: u32, scorer: usize },
+    /// The end of the last rally is shown again in slow motion, until a player skips it
+    InstantReplay { frames_left: u32 },
+    /// The match has a winner, nothing moves anymore
+    MatchOver { winner: usize },
+    /// A player opened the pause menu, nothing moves until the phase is restored
+    Paused,
+}
+
+impl Default for MatchPhase {
+    fn default() -> Self {
+        MatchPhase::PreMatch { frames_left: 0 }
+    }
+}
+
+impl MatchPhase {
+    /// Returns the phase a match with the given rules starts in
+    pub fn new(rules: &MatchRules) -> Self {
+        MatchPhase::PreMatch {
+            frames_left: rules.countdown_frames,
+        }
+    }
+
+    /// Returns true while the ball is in play
+    pub fn is_rally(&self) -> bool {
+        matches!(self, MatchPhase::Rally)
+    }
+
+    /// Returns true if the players may move, i.e. while serving and during rallies
+    pub fn players_can_move(&self) -> bool {
+        matches!(self, MatchPhase::Serving { .. } | MatchPhase::Rally)
+    }
+
+    /// Enters the phase following a point which was just awarded to `scorer` in the match state
+    pub fn on_point_scored(&mut self, match_state: &MatchState, scorer: usize) {
+        *self = match match_state.check_for_match_winner() {
+            Some(winner) => MatchPhase::MatchOver { winner },
+            None => MatchPhase::PointScored {
+                frames_left: match_state.rules().countdown_frames,
+                scorer,
+            },
+        };
+    }
+
+    /// Ends the instant replay, moving on to the next serve
+    pub fn skip_instant_replay(&mut self, rules: &MatchRules) {
+        if matches!(self, MatchPhase::InstantReplay { .. }) {
+            *self = MatchPhase::Serving {
+                frames_left: rules.serve_timer_frames,
+            };
+        }
+    }
+
+    /// Advances the timed phases by a frame. Returns true when the ball should be served this
+    /// frame, either because the server pressed serve or the serve timer ran out.
+    pub fn advance(&mut self, rules: &MatchRules, serve_pressed: bool) -> bool {
+        match *self {
+            MatchPhase::PreMatch { frames_left } if frames_left > 1 => {
+                *self = MatchPhase::PreMatch {
+                    frames_left: frames_left - 1,
+                };
+                false
+            }
+            MatchPhase::PointScored {
+                frames_left,
+                scorer,
+            } if frames_left > 1 => {
+                *self = MatchPhase::PointScored {
+                    frames_left: frames_left - 1,
+                    scorer,
+                };
+                false
+            }
+            MatchPhase::PointScored { .. } if rules.instant_replay_frames() > 0 => {
+                *self = MatchPhase::InstantReplay {
+                    frames_left: rules.instant_replay_frames(),
+                };
+                false
+            }
+            MatchPhase::InstantReplay { frames_left } if frames_left > 1 => {
+                *self = MatchPhase::InstantReplay {
+                    frames_left: frames_left - 1,
+                };
+                false
+            }
+            MatchPhase::PreMatch { .. }
+            | MatchPhase::PointScored { .. }
+            | MatchPhase::InstantReplay { .. } => {
+                *self = MatchPhase::Serving {
+                    frames_left: rules.serve_timer_frames,
+                };
+                false
+            }
+            MatchPhase::Serving { frames_left } if !serve_pressed && frames_left > 1 => {
+                *self = MatchPhase::Serving {
+                    frames_left: frames_left - 1,
+                };
+                false
+            }
+            MatchPhase::Serving { .. } => {
+                *self = MatchPhase::Rally;
+                true
+            }
+            MatchPhase::Rally | MatchPhase::MatchOver { .. } | MatchPhase::Paused => false,
+        }
+    }
+}
+
+/// Advances the timed phases. The ball is held above the serving player before the first serve
+/// and while serving, the server serves it by jumping or once the serve timer runs out. After a
+/// point the ball stays where it landed until the next serve, the instant replay in between is
+/// skipped when a human player jumps.
+///
+/// Runs after the ball collisions, so a point scored this frame is already reflected.
+pub fn update_match_phase(
+    entities: Res<Entities>,
+    players: Comp<Player>,
+    mut balls: CompMut<Ball>,
+    match_inputs: Res<MatchInputs>,
+    bot_players: Res<BotPlayers>,
+    match_state: Res<MatchState>,
+    mut match_phase: ResMut<MatchPhase>,
+    physics: Res<PhysicsMeta>,
+) {
+    if matches!(
+        *match_phase,
+        MatchPhase::Rally | MatchPhase::MatchOver { .. } | MatchPhase::Paused
+    ) {
+        return;
+    }
+
+    let server = match_state.server();
+    let serve_pressed = match_inputs.get_control(server).jump_just_pressed;
+    let skip_pressed = (0..2).any(|player_idx| {
+        bot_players.bots[player_idx].is_none()
+            && match_inputs.get_control(player_idx).jump_just_pressed
+    });
+    // The jump skipping the instant replay doesn't also serve
+    let serve = if skip_pressed && matches!(*match_phase, MatchPhase::InstantReplay { .. }) {
+        match_phase.skip_instant_replay(match_state.rules());
+        false
+    } else {
+        match_phase.advance(match_state.rules(), serve_pressed)
+    };
+    if matches!(
+        *match_phase,
+        MatchPhase::PointScored { .. } | MatchPhase::InstantReplay { .. }
+    ) {
+        return;
+    }
+
+    let Some((_player_ent, server_player)) = entities
+        .iter_with(&players)
+        .find(|(_ent, player)| player.idx == server)
+    else {
+        return;
+    };
+
+    let physics = physics.to_fixed();
+    for (_ent, ball) in entities.iter_with(&mut balls) {
+        ball.hold_at(server_player.position.x);
+        if serve {
+            ball.serve(&physics);
+        }
+    }
+}
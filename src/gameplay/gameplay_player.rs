@@ -1,4 +1,4 @@
-use super::{gameplay::*, Ball, MatchState};
+use super::{gameplay::*, Ball, Fixed, FixedVec2, MatchPhase, MatchState};
 use crate::input::MatchInputs;
 use bones_framework::prelude::*;
 
@@ -10,73 +10,103 @@ pub struct LocalPlayer {
     pub idx: u32,
 }
 
+/// The names of both players, only known in online matches
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct PlayerNames {
+    pub names: [String; 2],
+}
+
 /// Represents a player in the game
 #[derive(HasSchema, Default, Clone)]
 #[repr(C)]
 pub struct Player {
+    /// The simulated position of the player, the Transform is only synced from it for rendering
+    pub position: FixedVec2,
     /// The current velocity of the player
-    pub velocity: Vec2,
+    pub velocity: FixedVec2,
     /// Whether the player is touching the ground
     pub is_grounded: bool,
     /// The index of the player (0 or 1)
     pub idx: usize,
 }
 
+impl Player {
+    /// Returns the position players spawn at on the given side of the net (0 for left, 1 for right)
+    pub fn spawn_position(side: usize, physics: &FixedPhysics) -> FixedVec2 {
+        let spawn_x = if side == 0 {
+            -physics.spawn_offset_x
+        } else {
+            physics.spawn_offset_x
+        };
+        FixedVec2::new(spawn_x, physics.ground_level)
+    }
+}
+
 /// Handles player movement based on input and game state
 pub fn player_movement(
     entities: Res<Entities>,
     mut players: CompMut<Player>,
-    mut transforms: CompMut<Transform>,
     match_inputs: Res<MatchInputs>,
     match_state: Res<MatchState>,
+    match_phase: Res<MatchPhase>,
+    physics: Res<PhysicsMeta>,
 ) {
-    if match_state.is_finished() {
+    if !match_phase.players_can_move() {
         return;
     }
 
-    for (_ent, (player, transform)) in entities.iter_with((&mut players, &mut transforms)) {
+    let physics = physics.to_fixed();
+    for (_ent, player) in entities.iter_with(&mut players) {
         let player_control = match_inputs.get_control(player.idx);
+        let side = match_state.side_of(player.idx);
+
+        // Players switch sides between sets, move them to the spawn point of their new side
+        if (player.position.x < physics.center_boundary) != (side == 0) {
+            player.position = Player::spawn_position(side, &physics);
+            player.velocity = FixedVec2::ZERO;
+        }
 
         // Calculate horizontal movement
-        let movement = (player_control.right - player_control.left).clamp(-1.0, 1.0);
+        let movement =
+            Fixed::from_f32((player_control.right - player_control.left).clamp(-1.0, 1.0));
         let jump = player_control.jump_pressed;
 
         // Apply gravity
-        player.velocity.y -= GRAVITY;
+        player.velocity.y -= physics.gravity;
 
         // Set horizontal velocity
-        player.velocity.x = movement * MOVE_SPEED;
+        player.velocity.x = movement * physics.move_speed;
 
         // Handle jumping
         if jump && player.is_grounded {
-            player.velocity.y = JUMP_VELOCITY;
+            player.velocity.y = physics.jump_velocity;
             player.is_grounded = false;
         }
 
         // Update position
-        transform.translation.x += player.velocity.x;
-        transform.translation.y += player.velocity.y;
+        player.position += player.velocity;
 
         // Determine player boundaries
-        let (left_bound, right_bound) = if player.idx == 0 {
+        let half_width = physics.player_width / Fixed::from_int(2);
+        let (left_bound, right_bound) = if side == 0 {
             (
-                LEFT_BOUNDARY + PLAYER_WIDTH / 2.0,
-                CENTER_BOUNDARY - PLAYER_WIDTH / 2.0 - NET_WIDTH,
+                physics.left_boundary + half_width,
+                physics.center_boundary - half_width - physics.net_width,
             )
         } else {
             (
-                CENTER_BOUNDARY + NET_WIDTH + PLAYER_WIDTH / 2.0,
-                RIGHT_BOUNDARY - PLAYER_WIDTH / 2.0,
+                physics.center_boundary + physics.net_width + half_width,
+                physics.right_boundary - half_width,
             )
         };
 
         // Clamp player position within boundaries
-        transform.translation.x = transform.translation.x.clamp(left_bound, right_bound);
+        player.position.x = player.position.x.clamp(left_bound, right_bound);
 
         // Handle ground collision
-        if transform.translation.y <= GROUND_LEVEL {
-            transform.translation.y = GROUND_LEVEL;
-            player.velocity.y = 0.0;
+        if player.position.y <= physics.ground_level {
+            player.position.y = physics.ground_level;
+            player.velocity.y = Fixed::ZERO;
             player.is_grounded = true;
         } else {
             player.is_grounded = false;
@@ -88,77 +118,66 @@ pub fn player_movement(
 pub fn ball_player_collision(
     entities: Res<Entities>,
     mut balls: CompMut<Ball>,
-    mut transforms: CompMut<Transform>,
     players: Comp<Player>,
-    match_state: Res<MatchState>,
+    mut match_state: ResMut<MatchState>,
+    mut match_phase: ResMut<MatchPhase>,
+    physics: Res<PhysicsMeta>,
 ) {
-    if match_state.is_finished() {
+    if !match_phase.is_rally() {
         return;
     }
 
-    let mut ball_updates = Vec::new();
-    for (ball_ent, (_ball, ball_transform)) in entities.iter_with((&balls, &transforms)) {
-        let ball_center = Vec2::new(ball_transform.translation.x, ball_transform.translation.y);
-        for (_player_ent, (player, player_transform)) in entities.iter_with((&players, &transforms))
-        {
-            let player_center = Vec2::new(
-                player_transform.translation.x,
-                player_transform.translation.y + PLAYER_HEIGHT / 2.0,
-            );
-            let rel_x = ball_center.x - player_center.x;
-            let rel_y = ball_center.y - player_center.y;
+    let physics = physics.to_fixed();
+    let two = Fixed::from_int(2);
+    let half_width = physics.player_width / two;
+    let half_height = physics.player_height / two;
+
+    for (_ball_ent, ball) in entities.iter_with(&mut balls) {
+        for (_player_ent, player) in entities.iter_with(&players) {
+            let player_center = FixedVec2::new(player.position.x, player.position.y + half_height);
+            let rel_x = ball.position.x - player_center.x;
+            let rel_y = ball.position.y - player_center.y;
 
             // Check for collision
-            if rel_x.abs() < PLAYER_WIDTH / 2.0 + BALL_RADIUS
-                && rel_y.abs() < PLAYER_HEIGHT / 2.0 + BALL_RADIUS
+            if rel_x.abs() < half_width + physics.ball_radius
+                && rel_y.abs() < half_height + physics.ball_radius
             {
-                // Calculate relative position on the player
-                let mut relative_x_pos = rel_x / (PLAYER_WIDTH / 2.0);
-                if player.idx == 1 {
+                // Too many touches on one side give the point to the opponent
+                if let Some(scoring_player) = match_state.register_touch(player.idx) {
+                    match_state.award_point(scoring_player);
+                    match_phase.on_point_scored(&match_state, scoring_player);
+                    ball.velocity = FixedVec2::ZERO;
+                    break;
+                }
+
+                // Calculate relative position on the player, a player without width (from a
+                // zero player_width in the config) bounces the ball straight up
+                let is_right_side = match_state.side_of(player.idx) == 1;
+                let mut relative_x_pos = rel_x.checked_div(half_width).unwrap_or(Fixed::ZERO);
+                if is_right_side {
                     relative_x_pos = -relative_x_pos;
                 }
 
                 // Calculate bounce angle
-                let max_angle = std::f32::consts::FRAC_PI_4;
-                let bounce_angle = relative_x_pos * max_angle;
+                let bounce_angle = relative_x_pos * Fixed::FRAC_PI_4;
 
                 // Calculate new velocity
-                let speed = MAX_BALL_SPEED * PLAYER_BOUNCE_FACTOR;
+                let speed = physics.max_ball_speed * physics.player_bounce_factor;
                 let mut new_velocity =
-                    Vec2::new(bounce_angle.sin() * speed, bounce_angle.cos() * speed);
+                    FixedVec2::new(bounce_angle.sin() * speed, bounce_angle.cos() * speed);
 
-                if player.idx == 1 {
+                if is_right_side {
                     new_velocity.x = -new_velocity.x;
                 }
 
-                // Add player's velocity to the ball
-                let final_velocity = new_velocity + player.velocity * 0.5;
-
-                // Calculate new position
-                let new_position = Vec2::new(
-                    player_center.x + rel_x.signum() * (PLAYER_WIDTH / 2.0 + BALL_RADIUS + 1.0),
-                    player_center.y + PLAYER_HEIGHT / 2.0 + BALL_RADIUS + 1.0,
-                );
+                // Add player's velocity to the ball, then clamp ball speed
+                let final_velocity = new_velocity + player.velocity * (Fixed::ONE / two);
+                ball.velocity = final_velocity.clamp_length_max(physics.max_ball_speed);
 
-                ball_updates.push((ball_ent, final_velocity, new_position));
+                // Move the ball on top of the player
+                ball.position.y = player_center.y + half_height + physics.ball_radius + Fixed::ONE;
                 break;
             }
         }
     }
-
-    // Apply updates to balls
-    for (ball_ent, new_velocity, new_position) in ball_updates {
-        if let (Some(ball), Some(ball_transform)) =
-            (balls.get_mut(ball_ent), transforms.get_mut(ball_ent))
-        {
-            ball.velocity = new_velocity;
-            ball_transform.translation.y = new_position.y;
-
-            // Clamp ball speed
-            let speed = ball.velocity.length();
-            if speed > MAX_BALL_SPEED {
-                ball.velocity = ball.velocity.normalize() * MAX_BALL_SPEED;
-            }
-        }
-    }
 }
@@ -0,0 +1,62 @@
+use super::{gameplay::*, Ball, BotPlayers, MatchPhase, MatchState, Player};
+use crate::input::MatchInputs;
+use bones_framework::input::PlayerControls;
+use bones_framework::prelude::*;
+
+/// The rematch votes of both players after the match is over. Votes are cast through the match
+/// inputs (jump or enter), so they reach the other peer like any other input and are rolled back
+/// with the world. Leaving is done through the pause menu opened with escape, like during play.
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct RematchVotes {
+    /// Whether each player voted for a rematch
+    pub votes: [bool; 2],
+}
+
+/// Collects the rematch votes once the match is over and restarts the match in place when both
+/// players voted for it. Bots always vote for a rematch.
+pub fn handle_rematch_votes(
+    entities: Res<Entities>,
+    mut players: CompMut<Player>,
+    mut balls: CompMut<Ball>,
+    match_inputs: Res<MatchInputs>,
+    bot_players: Res<BotPlayers>,
+    mut match_state: ResMut<MatchState>,
+    mut match_phase: ResMut<MatchPhase>,
+    mut rematch_votes: ResMut<RematchVotes>,
+    physics: Res<PhysicsMeta>,
+) {
+    if !matches!(*match_phase, MatchPhase::MatchOver { .. }) {
+        return;
+    }
+
+    for player_idx in 0..2 {
+        let player_control = match_inputs.get_control(player_idx);
+        if player_control.jump_just_pressed
+            || player_control.enter_just_pressed
+            || bot_players.bots[player_idx].is_some()
+        {
+            rematch_votes.votes[player_idx] = true;
+        }
+    }
+    if !rematch_votes.votes.iter().all(|&vote| vote) {
+        return;
+    }
+
+    // Restart the match with the same rules, entities and socket
+    let rules = match_state.rules().clone();
+    *match_phase = MatchPhase::new(&rules);
+    *match_state = MatchState::new(rules);
+    *rematch_votes = RematchVotes::default();
+
+    let physics = physics.to_fixed();
+    for (_ent, player) in entities.iter_with(&mut players) {
+        player.position = Player::spawn_position(match_state.side_of(player.idx), &physics);
+        player.velocity = default();
+        player.is_grounded = true;
+    }
+    let server_position =
+        Player::spawn_position(match_state.side_of(match_state.server()), &physics);
+    for (_ent, ball) in entities.iter_with(&mut balls) {
+        ball.hold_at(server_position.x);
+    }
+}
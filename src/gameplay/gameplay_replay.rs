@@ -0,0 +1,154 @@
+use super::{BotPlayers, MatchPhase, SimulationFrame};
+use crate::input::{DensePlayerControl, MatchInputs};
+use crate::replay::Replay;
+use bones_framework::input::PlayerControls;
+use bones_framework::networking::input::NetworkPlayerControl;
+use bones_framework::prelude::*;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Resource recording the inputs of every frame, saved as a replay once the match ends.
+///
+/// The recording is shared between clones so that rollback snapshots keep writing to the same
+/// replay, re-simulated frames replace the inputs recorded while they were predicted.
+#[derive(HasSchema, Clone, Default)]
+pub struct ReplayRecorder {
+    recording: Option<Arc<Mutex<ReplayRecording>>>,
+}
+
+/// A replay being recorded
+struct ReplayRecording {
+    replay: Replay,
+    /// The file the replay is saved to, overwritten after every match of the session
+    path: PathBuf,
+    /// The frame at which the match ended, None while it is running
+    match_over_frame: Option<u32>,
+    /// Whether the replay was saved since the match ended
+    saved: bool,
+}
+
+impl ReplayRecorder {
+    /// Creates a recorder which records into the given replay
+    pub fn new(replay: Replay) -> Self {
+        let recording = ReplayRecording {
+            replay,
+            path: Replay::new_file_path(),
+            match_over_frame: None,
+            saved: false,
+        };
+        Self {
+            recording: Some(Arc::new(Mutex::new(recording))),
+        }
+    }
+
+    /// Returns a copy of the replay recorded so far
+    pub fn replay(&self) -> Option<Replay> {
+        let recording = self.recording.as_ref()?;
+        Some(recording.lock().unwrap().replay.clone())
+    }
+
+    /// Returns the recorded inputs from index `start` up to the given confirmed frame, the inputs
+    /// at index `i` are the ones simulated at frame `i + 1`
+    pub fn confirmed_inputs(
+        &self,
+        start: usize,
+        confirmed_frame: u32,
+    ) -> Vec<[DensePlayerControl; 2]> {
+        let Some(recording) = &self.recording else {
+            return Vec::new();
+        };
+        let inputs = &recording.lock().unwrap().replay.inputs;
+        let end = inputs.len().min(confirmed_frame as usize);
+        inputs
+            .get(start..end)
+            .map(<[_]>::to_vec)
+            .unwrap_or_default()
+    }
+
+    /// Saves the replay of a match which ended early, keeping only the inputs up to the given
+    /// confirmed frame and the winner by forfeit if there is one
+    pub fn save_unfinished(&self, confirmed_frame: u32, forfeit_winner: Option<usize>) {
+        let Some(recording) = &self.recording else {
+            return;
+        };
+        let mut recording = recording.lock().unwrap();
+        if recording.saved {
+            return;
+        }
+        recording.saved = true;
+
+        recording.replay.inputs.truncate(confirmed_frame as usize);
+        recording.replay.forfeit_winner = forfeit_winner.map(|winner| winner as u32);
+        match recording.replay.save(&recording.path) {
+            Ok(()) => println!("Saved replay to {}", recording.path.display()),
+            Err(err) => eprintln!(
+                "Failed to save replay {}: {}",
+                recording.path.display(),
+                err
+            ),
+        }
+    }
+}
+
+/// Records the inputs of the human players for the current frame, before the bots write theirs.
+/// Bots aren't recorded since they produce the same inputs again when the replay is played.
+pub fn record_replay_inputs(
+    replay_recorder: Res<ReplayRecorder>,
+    match_inputs: Res<MatchInputs>,
+    bot_players: Res<BotPlayers>,
+    simulation_frame: Res<SimulationFrame>,
+) {
+    let Some(recording) = &replay_recorder.recording else {
+        return;
+    };
+    let mut recording = recording.lock().unwrap();
+
+    // Frames re-simulated after a rollback replace their predicted inputs
+    let inputs = &mut recording.replay.inputs;
+    inputs.truncate(simulation_frame.frame.saturating_sub(1) as usize);
+    inputs.push([0, 1].map(|player_idx| {
+        if bot_players.bots[player_idx].is_some() {
+            default()
+        } else {
+            match_inputs.get_control(player_idx).get_dense_input()
+        }
+    }));
+}
+
+/// Saves the replay once the match is over and every frame until then has been confirmed
+pub fn save_replay_on_match_end(
+    replay_recorder: Res<ReplayRecorder>,
+    match_phase: Res<MatchPhase>,
+    simulation_frame: Res<SimulationFrame>,
+) {
+    let Some(recording) = &replay_recorder.recording else {
+        return;
+    };
+    let mut recording = recording.lock().unwrap();
+
+    match *match_phase {
+        MatchPhase::MatchOver { .. } => {
+            // Saved once GGRS can no longer roll back to the end of the match
+            let match_over_frame = *recording
+                .match_over_frame
+                .get_or_insert(simulation_frame.frame);
+            if !recording.saved && simulation_frame.confirmed_frame() >= match_over_frame {
+                recording.saved = true;
+                match recording.replay.save(&recording.path) {
+                    Ok(()) => println!("Saved replay to {}", recording.path.display()),
+                    Err(err) => eprintln!(
+                        "Failed to save replay {}: {}",
+                        recording.path.display(),
+                        err
+                    ),
+                }
+            }
+        }
+        // The match may be paused after it ended
+        MatchPhase::Paused => {}
+        _ => {
+            recording.match_over_frame = None;
+            recording.saved = false;
+        }
+    }
+}
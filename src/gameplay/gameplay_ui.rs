@@ -1,75 +1,207 @@
-use super::{activate_networking_debug_overlays, MatchState, NetworkingDebugMenuState};
+use super::{
+    activate_networking_debug_overlays, detect_desyncs, draw_disconnect_overlay,
+    draw_pause_overlay, handle_leave_match, monitor_opponent_connection, show_instant_replay,
+    ConnectionMonitor, DesyncDetector, MatchPhase, MatchState, NetworkingDebugMenuState,
+    PlayerNames, RematchVotes,
+};
+use crate::network_simulator::network_simulator_window;
+use crate::networking::{receive_reliable_messages, MatchSocket, FPS};
+use crate::replay::draw_replay_controls;
+use crate::spectator::{
+    draw_spectator_host, draw_spectator_status, host_spectators, SpectatorHost,
+};
+use crate::sync_test::draw_sync_test_report;
 use crate::SessionNames;
 use bones_framework::networking::debug::network_debug_window;
+use bones_framework::networking::NetworkMatchSocket;
 use bones_framework::prelude::*;
 use egui::{Color32, RichText};
 
 /// Initializes the gameplay_ui session
-pub fn initialize_gameplay_ui_session(sessions: &mut ResMut<Sessions>) {
+pub fn initialize_gameplay_ui_session(sessions: &mut Sessions, socket: Option<NetworkMatchSocket>) {
     let gameplay_ui_session = sessions.create(SessionNames::GAMEPLAY_UI);
+    // Runs after the gameplay session, so the instant replay is drawn over the synced transforms
+    gameplay_ui_session.priority = 1;
     gameplay_ui_session
         .world
         .init_resource::<NetworkingDebugMenuState>();
+    gameplay_ui_session.world.init_resource::<DesyncDetector>();
+    gameplay_ui_session
+        .world
+        .init_resource::<ConnectionMonitor>();
+    gameplay_ui_session.world.init_resource::<SpectatorHost>();
+    gameplay_ui_session.world.insert_resource(MatchSocket {
+        socket,
+        messages: Vec::new(),
+    });
 
     gameplay_ui_session
         .add_system_to_stage(CoreStage::First, network_debug_window)
-        .add_system_to_stage(Update, draw_winning_text)
+        .add_system_to_stage(CoreStage::First, network_simulator_window)
+        .add_system_to_stage(CoreStage::First, receive_reliable_messages)
+        .add_system_to_stage(Update, detect_desyncs)
+        .add_system_to_stage(Update, monitor_opponent_connection)
+        .add_system_to_stage(Update, handle_leave_match)
+        .add_system_to_stage(Update, host_spectators)
+        .add_system_to_stage(Update, show_instant_replay)
+        .add_system_to_stage(Update, draw_desync_banner)
+        .add_system_to_stage(Update, draw_match_phase)
         .add_system_to_stage(Update, draw_score_system)
+        .add_system_to_stage(Update, draw_pause_overlay)
+        .add_system_to_stage(Update, draw_replay_controls)
+        .add_system_to_stage(Update, draw_spectator_status)
+        .add_system_to_stage(Update, draw_spectator_host)
+        .add_system_to_stage(Update, draw_sync_test_report)
+        .add_system_to_stage(Update, draw_disconnect_overlay)
         .add_system_to_stage(Update, activate_networking_debug_overlays);
 }
 
-pub fn draw_winning_text(sessions: Res<Sessions>, ctx: Res<EguiCtx>) {
-    if let Some(session) = sessions.get(SessionNames::GAMEPLAY) {
-        let match_state = session
-            .world
-            .get_resource::<MatchState>()
-            .expect("MatchState resource not found");
-        if let Some(winner_idx) = match_state.check_for_match_winner() {
-            let winner_text = if winner_idx == 0 {
-                "Left Player Wins!"
-            } else {
-                "Right Player Wins!"
-            };
-
-            egui::CentralPanel::default()
-                .frame(egui::Frame::none())
-                .show(&ctx, |ui| {
-                    ui.vertical_centered(|ui| {
-                        ui.add_space(100.0);
-                        let text = RichText::new(winner_text)
-                            .color(Color32::YELLOW)
-                            .size(60.0)
-                            .strong();
-                        ui.label(text);
-                    });
-                });
-        }
-    }
-}
-
 pub fn draw_score_system(sessions: Res<Sessions>, ctx: Res<EguiCtx>) {
     if let Some(session) = sessions.get(SessionNames::GAMEPLAY) {
         let match_state = session
             .world
             .get_resource::<MatchState>()
             .expect("MatchState resource not found");
+        let player_names = session.world.get_resource::<PlayerNames>();
         egui::TopBottomPanel::top("score_panel")
             .frame(egui::Frame::none())
             .show(&ctx, |ui| {
                 ui.add_space(10.0);
+                // Scores are shown on the side each player currently plays on
+                let left_player = match_state.player_on_side(0);
+                let right_player = match_state.player_on_side(1);
                 ui.vertical_centered(|ui| {
+                    if let Some(player_names) = &player_names {
+                        let names_text = format!(
+                            "{}   vs   {}",
+                            player_names.names[left_player], player_names.names[right_player]
+                        );
+                        let text = RichText::new(names_text).size(28.0).color(Color32::WHITE);
+                        ui.label(text);
+                    }
                     let score_text = format!(
                         "{} - {}",
-                        match_state.get_player_score(0),
-                        match_state.get_player_score(1)
+                        match_state.get_player_score(left_player),
+                        match_state.get_player_score(right_player)
                     );
                     let text = RichText::new(score_text)
                         .size(72.0)
                         .color(Color32::WHITE)
                         .strong();
                     ui.label(text);
+
+                    if match_state.rules().best_of_sets > 1 {
+                        let sets_text = format!(
+                            "Sets {} - {}   (Set {} of {})",
+                            match_state.get_set_score(left_player),
+                            match_state.get_set_score(right_player),
+                            match_state.current_set(),
+                            match_state.rules().best_of_sets
+                        );
+                        let text = RichText::new(sets_text)
+                            .size(28.0)
+                            .color(Color32::LIGHT_GRAY);
+                        ui.label(text);
+                    }
                 });
                 ui.add_space(550.0);
             });
     }
 }
+
+/// Draws the text of the current match phase in the middle of the screen: the countdown before
+/// the first serve, who is serving, who scored the last point, the instant replay label and the
+/// winner of the match.
+pub fn draw_match_phase(sessions: Res<Sessions>, ctx: Res<EguiCtx>) {
+    let Some(session) = sessions.get(SessionNames::GAMEPLAY) else {
+        return;
+    };
+    let (Some(match_state), Some(match_phase)) = (
+        session.world.get_resource::<MatchState>(),
+        session.world.get_resource::<MatchPhase>(),
+    ) else {
+        return;
+    };
+    // Players are called by their names in online matches, by their side otherwise
+    let player_names = session.world.get_resource::<PlayerNames>();
+    let player_name = |player_idx: usize| match &player_names {
+        Some(player_names) => player_names.names[player_idx].clone(),
+        None if match_state.side_of(player_idx) == 0 => "Left Player".to_string(),
+        None => "Right Player".to_string(),
+    };
+
+    let (title, subtitle, color) = match *match_phase {
+        MatchPhase::PreMatch { frames_left } => {
+            let seconds_left = (frames_left as f32 / FPS).ceil().max(1.0);
+            (
+                format!("{}", seconds_left),
+                "Get Ready!".to_string(),
+                Color32::WHITE,
+            )
+        }
+        MatchPhase::Serving { .. } => (
+            String::new(),
+            format!("{} Serves", player_name(match_state.server())),
+            Color32::WHITE,
+        ),
+        MatchPhase::PointScored { scorer, .. } => (
+            "Point!".to_string(),
+            format!("{} Scores", player_name(scorer)),
+            Color32::LIGHT_GREEN,
+        ),
+        MatchPhase::InstantReplay { .. } => (
+            "REPLAY".to_string(),
+            "Jump: Skip".to_string(),
+            Color32::LIGHT_RED,
+        ),
+        MatchPhase::MatchOver { winner } => {
+            let votes = session
+                .world
+                .get_resource::<RematchVotes>()
+                .map(|rematch_votes| rematch_votes.votes)
+                .unwrap_or_default();
+            let mut subtitle = "Jump/Enter: Rematch    Escape: Leave match…".to_string();
+            for player_idx in [match_state.player_on_side(0), match_state.player_on_side(1)] {
+                if votes[player_idx] {
+                    subtitle += &format!("\n{} wants a rematch", player_name(player_idx));
+                }
+            }
+            (
+                format!("{} Wins!", player_name(winner)),
+                subtitle,
+                Color32::YELLOW,
+            )
+        }
+        MatchPhase::Rally | MatchPhase::Paused => return,
+    };
+
+    egui::CentralPanel::default()
+        .frame(egui::Frame::none())
+        .show(&ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(150.0);
+                let text = RichText::new(title).color(color).size(60.0).strong();
+                ui.label(text);
+                let text = RichText::new(subtitle).color(Color32::WHITE).size(28.0);
+                ui.label(text);
+            });
+        });
+}
+
+/// Draws a warning banner once the peers' simulations have diverged
+pub fn draw_desync_banner(desync_detector: Res<DesyncDetector>, ctx: Res<EguiCtx>) {
+    if let Some(desync_frame) = desync_detector.desync_frame {
+        egui::TopBottomPanel::bottom("desync_panel")
+            .frame(egui::Frame::none())
+            .show(&ctx, |ui| {
+                ui.vertical_centered(|ui| {
+                    let text = RichText::new(format!("DESYNC (frame {})", desync_frame))
+                        .color(Color32::RED)
+                        .size(40.0)
+                        .strong();
+                    ui.label(text);
+                });
+                ui.add_space(10.0);
+            });
+    }
+}
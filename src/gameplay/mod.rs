@@ -1,11 +1,31 @@
 pub mod gameplay;
+pub mod gameplay_bot;
 pub mod gameplay_debug_overlays;
+pub mod gameplay_desync;
+pub mod gameplay_disconnect;
+pub mod gameplay_fixed_math;
+pub mod gameplay_inputs_logging;
+pub mod gameplay_instant_replay;
 pub mod gameplay_other_entities;
+pub mod gameplay_pause;
+pub mod gameplay_phase;
 pub mod gameplay_player;
+pub mod gameplay_rematch;
+pub mod gameplay_replay;
 pub mod gameplay_ui;
 
 pub use gameplay::*;
+pub use gameplay_bot::*;
 pub use gameplay_debug_overlays::*;
+pub use gameplay_desync::*;
+pub use gameplay_disconnect::*;
+pub use gameplay_fixed_math::*;
+pub use gameplay_inputs_logging::*;
+pub use gameplay_instant_replay::*;
 pub use gameplay_other_entities::*;
+pub use gameplay_pause::*;
+pub use gameplay_phase::*;
 pub use gameplay_player::*;
+pub use gameplay_rematch::*;
+pub use gameplay_replay::*;
 pub use gameplay_ui::*;
@@ -4,15 +4,23 @@ use bones_framework::networking::proto::DenseMoveDirection;
 use bones_framework::prelude::*;
 use bytemuck::{Pod, Zeroable};
 use std::array;
+use std::collections::HashMap;
 
 /// Maximum number of players supported
 const MAX_PLAYERS: u32 = 2;
 
-/// Represents the source of player control input, to keep things simple we join keyboard/gamepads together.
+/// Represents the source of player control input. Online play and the menus join keyboard/gamepads
+/// together, while local play splits them so that two players can share one machine.
 #[derive(Debug, Clone, Copy, Default, HasSchema, Hash, Eq, PartialEq)]
 pub enum ControlSource {
     #[default]
     KeyboardAndGamepads,
+    /// The left half of the keyboard (WASD + Space)
+    KeyboardLeft,
+    /// The right half of the keyboard (Arrow keys + Right Shift)
+    KeyboardRight,
+    /// A single gamepad, identified by its gamepad index
+    Gamepad(u32),
 }
 
 /// Represents the current state of a player's controls
@@ -64,6 +72,16 @@ impl DensePlayerControl {
         Self(value)
     }
 
+    /// Creates a DensePlayerControl from its raw bits
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw bits of the control, e.g. for logging or storing inputs
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
     /// Returns the movement direction
     pub fn move_direction(&self) -> Vec2 {
         DenseMoveDirection::from((self.0 & 0xFFFF) as u16).0
@@ -128,9 +146,9 @@ impl NetworkPlayerControl<DensePlayerControl> for PlayerControl {
     }
 }
 
-/// Defines the key mappings for player controls
-#[derive(HasSchema, Clone, Debug)]
-pub struct PlayerControlMapping {
+/// Defines the key mappings for a single keyboard control source
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct KeyboardMapping {
     pub left: Vec<KeyCode>,
     pub right: Vec<KeyCode>,
     pub up: Vec<KeyCode>,
@@ -140,40 +158,169 @@ pub struct PlayerControlMapping {
     pub enter: Vec<KeyCode>,
 }
 
+impl KeyboardMapping {
+    /// Updates the pressed state of the control bound to the given key, if any
+    fn apply_key(&self, control: &mut PlayerControl, key: KeyCode, pressed: bool) {
+        if self.left.contains(&key) {
+            control.left_pressed = pressed;
+        } else if self.right.contains(&key) {
+            control.right_pressed = pressed;
+        } else if self.up.contains(&key) {
+            control.up_pressed = pressed;
+        } else if self.down.contains(&key) {
+            control.down_pressed = pressed;
+        } else if self.jump.contains(&key) {
+            control.jump_pressed = pressed;
+        } else if self.esc_start.contains(&key) {
+            control.esc_start_pressed = pressed;
+        } else if self.enter.contains(&key) {
+            control.enter_pressed = pressed;
+        }
+    }
+}
+
+/// Defines the key mappings for player controls
+#[derive(HasSchema, Clone, Debug)]
+pub struct PlayerControlMapping {
+    /// Keys used by the joined keyboard/gamepads source (menus and online play)
+    pub keyboard: KeyboardMapping,
+    /// Keys used by the left half of the keyboard during local play
+    pub keyboard_left: KeyboardMapping,
+    /// Keys used by the right half of the keyboard during local play
+    pub keyboard_right: KeyboardMapping,
+}
+
 impl Default for PlayerControlMapping {
     fn default() -> Self {
         Self {
-            left: vec![KeyCode::Left, KeyCode::A],
-            right: vec![KeyCode::Right, KeyCode::D],
-            up: vec![KeyCode::Up, KeyCode::W],
-            down: vec![KeyCode::Down, KeyCode::S],
-            jump: vec![KeyCode::Space, KeyCode::Z, KeyCode::L],
-            esc_start: vec![KeyCode::Escape],
-            enter: vec![KeyCode::Return],
+            keyboard: KeyboardMapping {
+                left: vec![KeyCode::Left, KeyCode::A],
+                right: vec![KeyCode::Right, KeyCode::D],
+                up: vec![KeyCode::Up, KeyCode::W],
+                down: vec![KeyCode::Down, KeyCode::S],
+                jump: vec![KeyCode::Space, KeyCode::Z, KeyCode::L],
+                esc_start: vec![KeyCode::Escape],
+                enter: vec![KeyCode::Return],
+            },
+            keyboard_left: KeyboardMapping {
+                left: vec![KeyCode::A],
+                right: vec![KeyCode::D],
+                up: vec![KeyCode::W],
+                down: vec![KeyCode::S],
+                jump: vec![KeyCode::Space],
+                esc_start: vec![KeyCode::Escape],
+                enter: vec![],
+            },
+            keyboard_right: KeyboardMapping {
+                left: vec![KeyCode::Left],
+                right: vec![KeyCode::Right],
+                up: vec![KeyCode::Up],
+                down: vec![KeyCode::Down],
+                jump: vec![KeyCode::RShift, KeyCode::RControl, KeyCode::Numpad0],
+                esc_start: vec![KeyCode::Escape],
+                enter: vec![KeyCode::Return],
+            },
         }
     }
 }
 
-/// Collects and manages player input
+/// Collects and manages player input, tracked separately for every control source
 #[derive(HasSchema, Clone)]
 pub struct PlayerInputCollector {
-    current_controls: PlayerControl,
-    last_controls: PlayerControl,
+    current_controls: HashMap<ControlSource, PlayerControl>,
+    last_controls: HashMap<ControlSource, PlayerControl>,
+    /// Returned for control sources which have not produced any input yet
+    idle_controls: PlayerControl,
 }
 
 impl PlayerInputCollector {
-    /// Returns the current player controls
+    /// Returns the current player controls of the joined keyboard/gamepads source
     pub fn get_current_controls(&self) -> &PlayerControl {
-        &self.current_controls
+        self.get_control(0, ControlSource::KeyboardAndGamepads)
     }
 }
 
 impl Default for PlayerInputCollector {
     fn default() -> Self {
+        let sources = [
+            ControlSource::KeyboardAndGamepads,
+            ControlSource::KeyboardLeft,
+            ControlSource::KeyboardRight,
+        ];
         Self {
-            current_controls: default(),
-            last_controls: default(),
+            current_controls: sources.iter().map(|s| (*s, default())).collect(),
+            last_controls: sources.iter().map(|s| (*s, default())).collect(),
+            idle_controls: default(),
+        }
+    }
+}
+
+/// Applies a single gamepad event to the given player control
+fn apply_gamepad_event(current_control: &mut PlayerControl, event: &GamepadEvent) {
+    match event {
+        GamepadEvent::Axis(axis_event) => {
+            if axis_event.axis == GamepadAxis::LeftStickX {
+                if axis_event.value < -0.2 {
+                    current_control.left = 1.0;
+                    current_control.right = 0.0;
+                    current_control.left_pressed = true;
+                    current_control.right_pressed = false;
+                } else if axis_event.value > 0.2 {
+                    current_control.right = 1.0;
+                    current_control.left = 0.0;
+                    current_control.right_pressed = true;
+                    current_control.left_pressed = false;
+                } else {
+                    current_control.left = 0.0;
+                    current_control.right = 0.0;
+                    current_control.left_pressed = false;
+                    current_control.right_pressed = false;
+                }
+            } else if axis_event.axis == GamepadAxis::LeftStickY {
+                if axis_event.value < -0.2 {
+                    current_control.down = 1.0;
+                    current_control.up = 0.0;
+                    current_control.down_pressed = true;
+                    current_control.up_pressed = false;
+                } else if axis_event.value > 0.2 {
+                    current_control.up = 1.0;
+                    current_control.down = 0.0;
+                    current_control.up_pressed = true;
+                    current_control.down_pressed = false;
+                } else {
+                    current_control.up = 0.0;
+                    current_control.down = 0.0;
+                    current_control.up_pressed = false;
+                    current_control.down_pressed = false;
+                }
+            }
         }
+        GamepadEvent::Button(button_event) => match button_event.button {
+            GamepadButton::DPadLeft => {
+                current_control.left = if button_event.value > 0.2 { 1.0 } else { 0.0 };
+                current_control.left_pressed = button_event.value > 0.2;
+            }
+            GamepadButton::DPadRight => {
+                current_control.right = if button_event.value > 0.2 { 1.0 } else { 0.0 };
+                current_control.right_pressed = button_event.value > 0.2;
+            }
+            GamepadButton::DPadUp => {
+                current_control.up = if button_event.value > 0.2 { 1.0 } else { 0.0 };
+                current_control.up_pressed = button_event.value > 0.2;
+            }
+            GamepadButton::DPadDown => {
+                current_control.down = if button_event.value > 0.2 { 1.0 } else { 0.0 };
+                current_control.down_pressed = button_event.value > 0.2;
+            }
+            GamepadButton::South => {
+                current_control.jump_pressed = button_event.value > 0.5;
+            }
+            GamepadButton::Start => {
+                current_control.esc_start_pressed = button_event.value > 0.5;
+            }
+            _ => {}
+        },
+        _ => {}
     }
 }
 
@@ -182,15 +329,18 @@ impl<'a> InputCollector<'a, PlayerControlMapping, ControlSource, PlayerControl>
 {
     /// Updates the "just pressed" states
     fn update_just_pressed(&mut self) {
-        let last = self.last_controls;
-        let current = &mut self.current_controls;
-
-        current.esc_start_just_pressed = current.esc_start_pressed && !last.esc_start_pressed;
-        current.moving =
-            current.left > 0.01 || current.right > 0.01 || current.up > 0.01 || current.down > 0.01;
-        current.jump_just_pressed = current.jump_pressed && !last.jump_pressed;
-        current.just_moved = current.moving && !last.moving;
-        current.enter_just_pressed = current.enter_pressed && !last.enter_pressed;
+        for (source, current) in self.current_controls.iter_mut() {
+            let last = self.last_controls.get(source).copied().unwrap_or_default();
+
+            current.esc_start_just_pressed = current.esc_start_pressed && !last.esc_start_pressed;
+            current.moving = current.left > 0.01
+                || current.right > 0.01
+                || current.up > 0.01
+                || current.down > 0.01;
+            current.jump_just_pressed = current.jump_pressed && !last.jump_pressed;
+            current.just_moved = current.moving && !last.moving;
+            current.enter_just_pressed = current.enter_pressed && !last.enter_pressed;
+        }
     }
 
     /// Advances to the next frame, updating last controls
@@ -205,128 +355,64 @@ impl<'a> InputCollector<'a, PlayerControlMapping, ControlSource, PlayerControl>
         keyboard: &KeyboardInputs,
         gamepad: &GamepadInputs,
     ) {
-        // Keyboard input
-        let current_control = &mut self.current_controls;
+        let keyboard_sources = [
+            (ControlSource::KeyboardAndGamepads, &mapping.keyboard),
+            (ControlSource::KeyboardLeft, &mapping.keyboard_left),
+            (ControlSource::KeyboardRight, &mapping.keyboard_right),
+        ];
 
         // Update pressed state based on key events
         for event in &keyboard.key_events {
-            match event.key_code {
-                Set(key) if mapping.left.contains(&key) => {
-                    current_control.left_pressed = event.button_state.pressed();
-                }
-                Set(key) if mapping.right.contains(&key) => {
-                    current_control.right_pressed = event.button_state.pressed();
-                }
-                Set(key) if mapping.up.contains(&key) => {
-                    current_control.up_pressed = event.button_state.pressed();
-                }
-                Set(key) if mapping.down.contains(&key) => {
-                    current_control.down_pressed = event.button_state.pressed();
-                }
-                Set(key) if mapping.jump.contains(&key) => {
-                    current_control.jump_pressed = event.button_state.pressed();
-                }
-                Set(key) if mapping.esc_start.contains(&key) => {
-                    current_control.esc_start_pressed = event.button_state.pressed();
-                }
-                Set(key) if mapping.enter.contains(&key) => {
-                    current_control.enter_pressed = event.button_state.pressed();
-                }
-                _ => {}
+            let Set(key) = event.key_code else {
+                continue;
+            };
+            for (source, keys) in keyboard_sources {
+                let current_control = self.current_controls.entry(source).or_default();
+                keys.apply_key(current_control, key, event.button_state.pressed());
             }
         }
 
         // Set movement values based on pressed state
-        current_control.left = if current_control.left_pressed {
-            1.0
-        } else {
-            0.0
-        };
-        current_control.right = if current_control.right_pressed {
-            1.0
-        } else {
-            0.0
-        };
-        current_control.up = if current_control.up_pressed { 1.0 } else { 0.0 };
-        current_control.down = if current_control.down_pressed {
-            1.0
-        } else {
-            0.0
-        };
-
-        // Now apply gamepad input
+        for current_control in self.current_controls.values_mut() {
+            current_control.left = if current_control.left_pressed {
+                1.0
+            } else {
+                0.0
+            };
+            current_control.right = if current_control.right_pressed {
+                1.0
+            } else {
+                0.0
+            };
+            current_control.up = if current_control.up_pressed { 1.0 } else { 0.0 };
+            current_control.down = if current_control.down_pressed {
+                1.0
+            } else {
+                0.0
+            };
+        }
+
+        // Now apply gamepad input, both to the joined source and to the gamepad's own source
         for event in &gamepad.gamepad_events {
-            match event {
-                GamepadEvent::Axis(axis_event) => {
-                    if axis_event.axis == GamepadAxis::LeftStickX {
-                        if axis_event.value < -0.2 {
-                            current_control.left = 1.0;
-                            current_control.right = 0.0;
-                            current_control.left_pressed = true;
-                            current_control.right_pressed = false;
-                        } else if axis_event.value > 0.2 {
-                            current_control.right = 1.0;
-                            current_control.left = 0.0;
-                            current_control.right_pressed = true;
-                            current_control.left_pressed = false;
-                        } else {
-                            current_control.left = 0.0;
-                            current_control.right = 0.0;
-                            current_control.left_pressed = false;
-                            current_control.right_pressed = false;
-                        }
-                    } else if axis_event.axis == GamepadAxis::LeftStickY {
-                        if axis_event.value < -0.2 {
-                            current_control.down = 1.0;
-                            current_control.up = 0.0;
-                            current_control.down_pressed = true;
-                            current_control.up_pressed = false;
-                        } else if axis_event.value > 0.2 {
-                            current_control.up = 1.0;
-                            current_control.down = 0.0;
-                            current_control.up_pressed = true;
-                            current_control.down_pressed = false;
-                        } else {
-                            current_control.up = 0.0;
-                            current_control.down = 0.0;
-                            current_control.up_pressed = false;
-                            current_control.down_pressed = false;
-                        }
-                    }
-                }
-                GamepadEvent::Button(button_event) => match button_event.button {
-                    GamepadButton::DPadLeft => {
-                        current_control.left = if button_event.value > 0.2 { 1.0 } else { 0.0 };
-                        current_control.left_pressed = button_event.value > 0.2;
-                    }
-                    GamepadButton::DPadRight => {
-                        current_control.right = if button_event.value > 0.2 { 1.0 } else { 0.0 };
-                        current_control.right_pressed = button_event.value > 0.2;
-                    }
-                    GamepadButton::DPadUp => {
-                        current_control.up = if button_event.value > 0.2 { 1.0 } else { 0.0 };
-                        current_control.up_pressed = button_event.value > 0.2;
-                    }
-                    GamepadButton::DPadDown => {
-                        current_control.down = if button_event.value > 0.2 { 1.0 } else { 0.0 };
-                        current_control.down_pressed = button_event.value > 0.2;
-                    }
-                    GamepadButton::South => {
-                        current_control.jump_pressed = button_event.value > 0.5;
-                    }
-                    GamepadButton::Start => {
-                        current_control.esc_start_pressed = button_event.value > 0.5;
-                    }
-                    _ => {}
-                },
-                _ => {}
+            let gamepad_idx = match event {
+                GamepadEvent::Axis(axis_event) => axis_event.gamepad,
+                GamepadEvent::Button(button_event) => button_event.gamepad,
+                _ => continue,
+            };
+            for source in [
+                ControlSource::KeyboardAndGamepads,
+                ControlSource::Gamepad(gamepad_idx),
+            ] {
+                apply_gamepad_event(self.current_controls.entry(source).or_default(), event);
             }
         }
     }
 
-    /// Gets the current control state
-    fn get_control(&self, _player_idx: usize, _control_source: ControlSource) -> &PlayerControl {
-        &self.current_controls
+    /// Gets the current control state of the given control source
+    fn get_control(&self, _player_idx: usize, control_source: ControlSource) -> &PlayerControl {
+        self.current_controls
+            .get(&control_source)
+            .unwrap_or(&self.idle_controls)
     }
 }
 
@@ -0,0 +1,96 @@
+use crate::input::{ControlSource, MatchInputs, PlayerControlMapping, PlayerInputCollector};
+use crate::networking::FPS;
+use bones_framework::input::{InputCollector, PlayerControls};
+use bones_framework::networking::input::NetworkPlayerControl;
+use bones_framework::prelude::*;
+
+/// The maximum number of frames simulated in a single step, prevents spiraling after a long hitch
+const MAX_FRAMES_PER_STEP: u32 = 5;
+
+/// Session runner for local (non-GGRS) matches where both players share one machine.
+///
+/// Each player reads from its own control source, and the inputs go through the same dense
+/// representation used by the network so that gameplay systems behave identically in both modes.
+/// Players without a control source are left untouched so that a bot can control them.
+pub struct LocalSessionRunner {
+    /// The control source used by each player, None for bot-controlled players
+    pub control_sources: [Option<ControlSource>; 2],
+    /// Time accumulated since the last simulated frame
+    accumulator: f64,
+    /// The time of the last step, None before the first step
+    last_run: Option<Instant>,
+}
+
+impl LocalSessionRunner {
+    /// Creates a new LocalSessionRunner with the given control source for each player
+    pub fn new(control_sources: [Option<ControlSource>; 2]) -> Self {
+        Self {
+            control_sources,
+            accumulator: 0.0,
+            last_run: None,
+        }
+    }
+}
+
+impl SessionRunner for LocalSessionRunner {
+    /// Collects inputs for both players and runs the simulation at a fixed FPS
+    fn step(&mut self, now: Instant, world: &mut World, stages: &mut SystemStages) {
+        let frame_time = 1.0 / FPS as f64;
+        let last_run = self.last_run.unwrap_or(now);
+        self.accumulator += now.duration_since(last_run).as_secs_f64();
+        self.last_run = Some(now);
+
+        // Gather the latest keyboard/gamepad state for every control source
+        {
+            let keyboard = world.resource::<KeyboardInputs>();
+            let gamepad = world.resource::<GamepadInputs>();
+            let mapping = world.resource::<PlayerControlMapping>();
+            let mut input_collector = world.resource_mut::<PlayerInputCollector>();
+            input_collector.apply_inputs(&mapping, &keyboard, &gamepad);
+            input_collector.update_just_pressed();
+            input_collector.advance_frame();
+        }
+
+        let mut frames_run = 0;
+        while self.accumulator >= frame_time && frames_run < MAX_FRAMES_PER_STEP {
+            self.accumulator -= frame_time;
+            frames_run += 1;
+
+            // Feed each player's control through its dense form, same as the GGRS runner does
+            {
+                let input_collector = world.resource::<PlayerInputCollector>();
+                let mut match_inputs = world.resource_mut::<MatchInputs>();
+                for (player_idx, control_source) in self.control_sources.iter().enumerate() {
+                    let Some(control_source) = control_source else {
+                        continue;
+                    };
+                    let dense = input_collector
+                        .get_control(player_idx, *control_source)
+                        .get_dense_input();
+                    match_inputs
+                        .get_control_mut(player_idx)
+                        .update_from_dense(&dense);
+                }
+            }
+
+            world
+                .resource_mut::<Time>()
+                .advance_exact(std::time::Duration::from_secs_f64(frame_time));
+            stages.run(world);
+        }
+
+        // Drop any time we could not catch up on
+        if frames_run == MAX_FRAMES_PER_STEP {
+            self.accumulator = 0.0;
+        }
+    }
+
+    /// Resets the frame timing so the restarted session does not try to catch up
+    fn restart_session(&mut self) {
+        self.accumulator = 0.0;
+        self.last_run = None;
+    }
+
+    /// Local input is always read directly from the input collector
+    fn disable_local_input(&mut self, _input_disabled: bool) {}
+}
@@ -1,13 +1,28 @@
 #![allow(clippy::too_many_arguments)]
+pub mod cli;
 pub mod gameplay;
 pub mod input;
+pub mod local_play;
 pub mod menu;
+pub mod network_simulator;
 pub mod networking;
+pub mod replay;
+pub mod settings;
+pub mod spectator;
+pub mod sync_test;
 
+#[cfg(test)]
+mod tests;
+
+use cli::CliArgs;
 use gameplay::GameplayMeta;
 pub use input::*;
+pub use local_play::*;
 pub use menu::*;
+use network_simulator::NetworkSimulator;
 pub use networking::*;
+use settings::MatchmakerSettings;
+pub use sync_test::*;
 
 use bones_bevy_renderer::{bevy::diagnostic::LogDiagnosticsPlugin, BonesBevyRenderer};
 use bones_framework::prelude::*;
@@ -53,13 +68,33 @@ pub fn create_game() -> Game {
         .init_shared_resource::<AssetServer>()
         .register_default_assets();
 
-    // Register the schema of all Metas
+    // Register the schema of all Metas and of the settings kept in Storage
     GameMeta::register_schema();
+    MatchmakerSettings::register_schema();
+
+    // Parse the command-line arguments and share them with every session
+    let cli_args = CliArgs::from_env();
+    let auto_matchmaking = cli_args.auto_matchmaking;
+    let input_delay_frames = cli_args.input_delay_frames;
+    game.insert_shared_resource(NetworkSimulator::new(cli_args.network_conditions.clone()));
+    game.insert_shared_resource(cli_args);
 
     // Create the main menu session and install the menu plugin
-    game.sessions
-        .create(SessionNames::MAIN_MENU)
-        .install_plugin(menu_plugin);
+    let menu_session = game.sessions.create(SessionNames::MAIN_MENU);
+    menu_session.install_plugin(menu_plugin);
+
+    // Skip straight to searching for an online match if requested
+    if auto_matchmaking {
+        menu_session.world.insert_resource(MenuData {
+            state: MenuState::OnlinePlayConfig,
+            input_delay_frames,
+            ..default()
+        });
+        menu_session.world.insert_resource(NetworkGameState {
+            status: NetworkGameStatus::Searching,
+            ..default()
+        });
+    }
 
     game
 }
@@ -1,18 +1,42 @@
+use crate::gameplay::{BotPlayers, GameplayPlugin, GameplaySprites};
 use crate::input::{ControlSource, PlayerControlMapping, PlayerInputCollector};
+use crate::local_play::LocalSessionRunner;
+use crate::replay::{start_replay_session, Replay, ReplayBrowser};
+use crate::settings::MatchmakerSettings;
+use crate::spectator::{handle_spectator_join, SpectatorJoin};
+use crate::sync_test::start_sync_test_session;
 use crate::{
-    networking::{handle_online_menu_matchmaking, NetworkGameState, NetworkGameStatus},
+    cli::CliArgs,
+    networking::{
+        handle_lan_menu_matchmaking, handle_online_menu_matchmaking, recommended_input_delay,
+        LanGameState, NetworkGameState, NetworkGameStatus, AUTO_INPUT_DELAY, MAX_INPUT_DELAY,
+        MAX_MATCHMAKING_RETRIES,
+    },
     GameMeta,
 };
+use bones_framework::networking::online::SearchState;
 use bones_framework::prelude::*;
 use egui::{Color32, RichText};
 use std::time::Duration;
 
+/// The number of replays listed at once in the replay browser
+const REPLAYS_SHOWN: usize = 8;
+
 /// Represents the current state of the menu
 #[derive(HasSchema, Clone, Default)]
 pub enum MenuState {
     #[default]
     MainMenu,
     OnlinePlayConfig,
+    LanPlayConfig,
+    DirectConnectConfig,
+    DirectJoinConfig,
+    DirectSpectateConfig,
+    LocalPlayConfig,
+    SinglePlayerConfig,
+    MatchmakerSettings,
+    MatchmakerInput,
+    Replays,
 }
 
 /// Holds data related to the menu state and configuration
@@ -22,7 +46,18 @@ pub struct MenuData {
     pub state: MenuState,
     pub selected_option: usize,
     pub scroll_timer: Duration,
+    /// The input delay of online matches, AUTO_INPUT_DELAY picks it from the measured ping
     pub input_delay_frames: usize,
+    /// Whether local play uses two gamepads instead of a split keyboard
+    pub local_use_gamepads: bool,
+    /// The index of the selected bot difficulty in GameplayMeta
+    pub bot_difficulty_idx: usize,
+    /// The connection ticket entered to join a hosted game directly
+    pub ticket_input: String,
+    /// The matchmaker node ID being entered in the settings
+    pub matchmaker_input: String,
+    /// Why the entered matchmaker node ID was rejected, empty if it wasn't
+    pub matchmaker_error: String,
 }
 
 impl Default for MenuData {
@@ -32,7 +67,12 @@ impl Default for MenuData {
             state: MenuState::MainMenu,
             selected_option: 0,
             scroll_timer: Duration::ZERO,
-            input_delay_frames: 2,
+            input_delay_frames: AUTO_INPUT_DELAY,
+            local_use_gamepads: false,
+            bot_difficulty_idx: 0,
+            ticket_input: String::new(),
+            matchmaker_input: String::new(),
+            matchmaker_error: String::new(),
         }
     }
 }
@@ -44,12 +84,19 @@ pub fn menu_plugin(session: &mut Session) {
     session.world.init_resource::<PlayerControlMapping>();
     session.world.init_resource::<MenuData>();
     session.world.init_resource::<NetworkGameState>();
+    session.world.init_resource::<LanGameState>();
+    session.world.init_resource::<ReplayBrowser>();
+    session.world.init_resource::<SpectatorJoin>();
 
     session
         .add_system_to_stage(Update, handle_menu_input)
         .add_system_to_stage(Update, menu_selection_system)
         .add_system_to_stage(Update, menu_draw_system)
         .add_system_to_stage(Update, handle_online_menu_matchmaking)
+        .add_system_to_stage(Update, handle_lan_menu_matchmaking)
+        .add_system_to_stage(Update, handle_spectator_join)
+        .add_system_to_stage(Update, handle_cli_sync_test)
+        .add_system_to_stage(Update, handle_cli_replay)
         .add_startup_system(menu_startup);
 }
 
@@ -57,8 +104,14 @@ pub fn menu_plugin(session: &mut Session) {
 fn menu_selection_system(
     mut menu_data: ResMut<MenuData>,
     mut network_state: ResMut<NetworkGameState>,
+    mut lan_state: ResMut<LanGameState>,
+    mut replay_browser: ResMut<ReplayBrowser>,
+    mut session_options: ResMut<SessionOptions>,
+    mut sessions: ResMut<Sessions>,
+    mut storage: ResMut<Storage>,
     input_collector: Res<PlayerInputCollector>,
     time: Res<Time>,
+    meta: Root<GameMeta>,
 ) {
     let player_control = input_collector.get_control(0, ControlSource::KeyboardAndGamepads);
 
@@ -72,18 +125,83 @@ fn menu_selection_system(
                     menu_data.selected_option = menu_data.selected_option.saturating_sub(1);
                     menu_data.scroll_timer = Duration::from_millis(200);
                 } else if player_control.down_pressed {
-                    menu_data.selected_option = (menu_data.selected_option + 1).min(1);
+                    menu_data.selected_option = (menu_data.selected_option + 1).min(8);
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                }
+            }
+            MenuState::LanPlayConfig => {
+                // Handle navigation between hosting and the discovered games
+                if player_control.up_pressed {
+                    menu_data.selected_option = menu_data.selected_option.saturating_sub(1);
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                } else if player_control.down_pressed {
+                    menu_data.selected_option =
+                        (menu_data.selected_option + 1).min(lan_state.servers.len());
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                }
+            }
+            MenuState::DirectConnectConfig => {
+                // Handle navigation between hosting, joining and spectating
+                if player_control.up_pressed {
+                    menu_data.selected_option = menu_data.selected_option.saturating_sub(1);
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                } else if player_control.down_pressed {
+                    menu_data.selected_option = (menu_data.selected_option + 1).min(2);
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                }
+            }
+            MenuState::MatchmakerSettings => {
+                // Handle navigation between the default, saved and new matchmakers
+                let max_idx = MatchmakerSettings::load(&storage).servers.len() + 1;
+                if player_control.up_pressed {
+                    menu_data.selected_option = menu_data.selected_option.saturating_sub(1);
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                } else if player_control.down_pressed {
+                    menu_data.selected_option = (menu_data.selected_option + 1).min(max_idx);
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                }
+            }
+            MenuState::Replays => {
+                // Handle navigation between the saved replays
+                if player_control.up_pressed {
+                    menu_data.selected_option = menu_data.selected_option.saturating_sub(1);
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                } else if player_control.down_pressed {
+                    menu_data.selected_option = (menu_data.selected_option + 1)
+                        .min(replay_browser.files.len().saturating_sub(1));
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                }
+            }
+            // The keyboard is used to type the ticket or node ID
+            MenuState::DirectJoinConfig
+            | MenuState::DirectSpectateConfig
+            | MenuState::MatchmakerInput => {}
+            MenuState::LocalPlayConfig => {
+                // Handle control scheme toggling
+                if player_control.left_pressed || player_control.right_pressed {
+                    menu_data.local_use_gamepads = !menu_data.local_use_gamepads;
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                }
+            }
+            MenuState::SinglePlayerConfig => {
+                // Handle bot difficulty selection
+                let max_idx = meta.gameplay.bot_difficulties.len().saturating_sub(1);
+                if player_control.left_pressed {
+                    menu_data.bot_difficulty_idx = menu_data.bot_difficulty_idx.saturating_sub(1);
+                    menu_data.scroll_timer = Duration::from_millis(200);
+                } else if player_control.right_pressed {
+                    menu_data.bot_difficulty_idx = (menu_data.bot_difficulty_idx + 1).min(max_idx);
                     menu_data.scroll_timer = Duration::from_millis(200);
                 }
             }
             MenuState::OnlinePlayConfig => {
                 // Handle input delay adjustment
                 if player_control.left_pressed {
-                    menu_data.input_delay_frames =
-                        menu_data.input_delay_frames.saturating_sub(1).max(1);
+                    menu_data.input_delay_frames = menu_data.input_delay_frames.saturating_sub(1);
                     menu_data.scroll_timer = Duration::from_millis(200);
                 } else if player_control.right_pressed {
-                    menu_data.input_delay_frames = (menu_data.input_delay_frames + 1).min(60);
+                    menu_data.input_delay_frames =
+                        (menu_data.input_delay_frames + 1).min(MAX_INPUT_DELAY);
                     menu_data.scroll_timer = Duration::from_millis(200);
                 }
             }
@@ -100,6 +218,40 @@ fn menu_selection_system(
                         menu_data.selected_option = 0;
                     }
                     1 => {
+                        menu_data.state = MenuState::LanPlayConfig;
+                        menu_data.selected_option = 0;
+                    }
+                    2 => {
+                        menu_data.state = MenuState::DirectConnectConfig;
+                        menu_data.selected_option = 0;
+                    }
+                    3 => {
+                        menu_data.state = MenuState::LocalPlayConfig;
+                        menu_data.selected_option = 0;
+                    }
+                    4 => {
+                        menu_data.state = MenuState::SinglePlayerConfig;
+                        menu_data.selected_option = 0;
+                    }
+                    5 => {
+                        menu_data.state = MenuState::MatchmakerSettings;
+                        menu_data.selected_option = 0;
+                    }
+                    6 => {
+                        menu_data.state = MenuState::Replays;
+                        menu_data.selected_option = 0;
+                        replay_browser.refresh();
+                    }
+                    7 => {
+                        // Use a different seed every run to cover more input sequences
+                        let seed = std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .map(|elapsed| elapsed.subsec_nanos())
+                            .unwrap_or(1);
+                        session_options.delete = true;
+                        start_sync_test_session(&mut sessions, &meta, seed);
+                    }
+                    8 => {
                         println!("Exiting game...");
                         std::process::exit(0);
                     }
@@ -109,30 +261,207 @@ fn menu_selection_system(
                     // Trigger the match making logic
                     network_state.status = NetworkGameStatus::Searching;
                 }
+                MenuState::LanPlayConfig => {
+                    // The first option hosts a game, the others join a discovered one
+                    if menu_data.selected_option == 0 {
+                        network_state.status = NetworkGameStatus::Hosting;
+                    } else {
+                        match lan_state.join(menu_data.selected_option - 1) {
+                            Ok(()) => network_state.status = NetworkGameStatus::Joining,
+                            Err(reason) => network_state.fail(reason),
+                        }
+                    }
+                }
+                MenuState::DirectConnectConfig => match menu_data.selected_option {
+                    0 => network_state.status = NetworkGameStatus::Hosting,
+                    1 => menu_data.state = MenuState::DirectJoinConfig,
+                    _ => menu_data.state = MenuState::DirectSpectateConfig,
+                },
+                MenuState::DirectJoinConfig | MenuState::DirectSpectateConfig => {
+                    // Space may be part of the typed text, so only Enter joins
+                    if player_control.enter_just_pressed {
+                        let spectate = matches!(menu_data.state, MenuState::DirectSpectateConfig);
+                        match lan_state.join_ticket(&menu_data.ticket_input, spectate) {
+                            Ok(()) => network_state.status = NetworkGameStatus::Joining,
+                            Err(reason) => network_state.fail(reason),
+                        }
+                    }
+                }
+                MenuState::MatchmakerSettings => {
+                    // Pick the default or a saved matchmaker, the last option adds a new one
+                    let mut settings = MatchmakerSettings::load(&storage);
+                    match menu_data.selected_option {
+                        0 => settings.selected.clear(),
+                        i if i <= settings.servers.len() => {
+                            settings.selected = settings.servers[i - 1].clone();
+                        }
+                        _ => {
+                            menu_data.state = MenuState::MatchmakerInput;
+                            menu_data.matchmaker_input.clear();
+                            menu_data.matchmaker_error.clear();
+                        }
+                    }
+                    settings.save(&mut storage);
+                }
+                MenuState::MatchmakerInput => {
+                    // Space may be part of the typed text, so only Enter saves
+                    if player_control.enter_just_pressed {
+                        let mut settings = MatchmakerSettings::load(&storage);
+                        match settings.add(&menu_data.matchmaker_input) {
+                            Ok(()) => {
+                                settings.save(&mut storage);
+                                menu_data.state = MenuState::MatchmakerSettings;
+                                menu_data.selected_option = settings.servers.len();
+                            }
+                            Err(reason) => menu_data.matchmaker_error = reason,
+                        }
+                    }
+                }
+                MenuState::Replays => {
+                    if let Some(path) = replay_browser.files.get(menu_data.selected_option) {
+                        match Replay::load(path)
+                            .and_then(|replay| start_replay_session(&mut sessions, &meta, replay))
+                        {
+                            Ok(()) => session_options.delete = true,
+                            Err(reason) => {
+                                replay_browser.error =
+                                    format!("Can't play {}: {}", path.display(), reason);
+                            }
+                        }
+                    }
+                }
+                MenuState::LocalPlayConfig => {
+                    // Start a local match with a separate control source per player
+                    let control_sources = if menu_data.local_use_gamepads {
+                        [ControlSource::Gamepad(0), ControlSource::Gamepad(1)]
+                    } else {
+                        [ControlSource::KeyboardLeft, ControlSource::KeyboardRight]
+                    };
+                    let session_runner =
+                        Box::new(LocalSessionRunner::new(control_sources.map(Some)));
+                    session_options.delete = true;
+                    GameplayPlugin::start_gameplay_session(
+                        &mut sessions,
+                        session_runner,
+                        Some(0),
+                        default(),
+                        meta.gameplay.physics.clone(),
+                        meta.gameplay.rules.clone(),
+                        GameplaySprites::from_meta(&meta.gameplay),
+                        None,
+                        Some(0),
+                    );
+                }
+                MenuState::SinglePlayerConfig => {
+                    // Start a local match with the right player controlled by a bot
+                    if let Some(difficulty) = meta
+                        .gameplay
+                        .bot_difficulties
+                        .get(menu_data.bot_difficulty_idx)
+                    {
+                        let session_runner = Box::new(LocalSessionRunner::new([
+                            Some(ControlSource::KeyboardAndGamepads),
+                            None,
+                        ]));
+                        session_options.delete = true;
+                        GameplayPlugin::start_gameplay_session(
+                            &mut sessions,
+                            session_runner,
+                            Some(0),
+                            BotPlayers::with_bot(1, difficulty.clone()),
+                            meta.gameplay.physics.clone(),
+                            meta.gameplay.rules.clone(),
+                            GameplaySprites::from_meta(&meta.gameplay),
+                            None,
+                            Some(0),
+                        );
+                    }
+                }
             }
         } else if player_control.esc_start_pressed {
-            // Return to main menu from online config submenu
-            if matches!(menu_data.state, MenuState::OnlinePlayConfig) {
+            // Return to main menu from the config submenus
+            if matches!(
+                menu_data.state,
+                MenuState::OnlinePlayConfig
+                    | MenuState::LanPlayConfig
+                    | MenuState::DirectConnectConfig
+                    | MenuState::DirectJoinConfig
+                    | MenuState::DirectSpectateConfig
+                    | MenuState::LocalPlayConfig
+                    | MenuState::SinglePlayerConfig
+                    | MenuState::MatchmakerSettings
+                    | MenuState::MatchmakerInput
+                    | MenuState::Replays
+            ) {
                 menu_data.state = MenuState::MainMenu;
                 menu_data.selected_option = 0;
             }
         }
     }
+    // Dismiss a connection failure, staying in the current menu to try again
+    else if network_state.status.is_error() {
+        if player_control.enter_just_pressed || player_control.esc_start_pressed {
+            network_state.reset();
+        }
+    }
     // If searching for an online match, allow exiting matchmaking
     else {
         if player_control.esc_start_pressed {
-            network_state.status = NetworkGameStatus::Idle;
+            network_state.cancel();
             menu_data.state = MenuState::MainMenu;
         }
     }
 }
 
+/// Starts the sync test requested through the `--sync-test` flag, only once per launch
+fn handle_cli_sync_test(
+    mut cli_args: ResMut<CliArgs>,
+    mut sessions: ResMut<Sessions>,
+    mut session_options: ResMut<SessionOptions>,
+    meta: Root<GameMeta>,
+) {
+    if let Some(seed) = cli_args.sync_test.take() {
+        session_options.delete = true;
+        start_sync_test_session(&mut sessions, &meta, seed);
+    }
+}
+
+/// Plays the replay requested through the `--replay` flag, only once per launch. If it can't be
+/// played, the replay browser is opened with the reason.
+fn handle_cli_replay(
+    mut cli_args: ResMut<CliArgs>,
+    mut menu_data: ResMut<MenuData>,
+    mut replay_browser: ResMut<ReplayBrowser>,
+    mut sessions: ResMut<Sessions>,
+    mut session_options: ResMut<SessionOptions>,
+    meta: Root<GameMeta>,
+) {
+    let Some(path) = cli_args.replay.take() else {
+        return;
+    };
+    match Replay::load(&path).and_then(|replay| start_replay_session(&mut sessions, &meta, replay))
+    {
+        Ok(()) => session_options.delete = true,
+        Err(reason) => {
+            eprintln!("Can't play replay {}: {}", path.display(), reason);
+            menu_data.state = MenuState::Replays;
+            menu_data.selected_option = 0;
+            replay_browser.refresh();
+            replay_browser.error = format!("Can't play {}: {}", path.display(), reason);
+        }
+    }
+}
+
 /// Draws the menu UI
 fn menu_draw_system(
     meta: Root<GameMeta>,
     ctx: Res<EguiCtx>,
-    menu_data: Res<MenuData>,
+    mut menu_data: ResMut<MenuData>,
     network_state: Res<NetworkGameState>,
+    lan_state: Res<LanGameState>,
+    replay_browser: Res<ReplayBrowser>,
+    storage: Res<Storage>,
+    cli_args: Res<CliArgs>,
 ) {
     egui::CentralPanel::default().show(&ctx, |ui| {
         ui.vertical_centered(|ui| {
@@ -142,7 +471,17 @@ fn menu_draw_system(
 
             match menu_data.state {
                 MenuState::MainMenu => {
-                    let options = ["Online Play", "Exit"];
+                    let options = [
+                        "Online Play",
+                        "LAN Play",
+                        "Direct Connect",
+                        "Local Play",
+                        "Single Player",
+                        "Matchmaker",
+                        "Replays",
+                        "Sync Test",
+                        "Exit",
+                    ];
                     for (i, option) in options.iter().enumerate() {
                         let text = if i == menu_data.selected_option {
                             format!("> {} <", option)
@@ -153,23 +492,258 @@ fn menu_draw_system(
                     }
                 }
                 MenuState::OnlinePlayConfig => {
+                    let input_delay = if menu_data.input_delay_frames == AUTO_INPUT_DELAY {
+                        "Auto (from ping)".to_string()
+                    } else {
+                        menu_data.input_delay_frames.to_string()
+                    };
+                    ui.label(menu_small_text(format!(
+                        "Input Delay Frames: < {} >",
+                        input_delay
+                    )));
+                }
+                MenuState::LanPlayConfig => {
+                    let host_text = "Host Game".to_string();
+                    let join_texts = lan_state.servers.iter().map(|server| {
+                        let ping = server
+                            .ping
+                            .map(|ping| format!("{}ms", ping))
+                            .unwrap_or_else(|| "?".to_string());
+                        format!("Join {} ({})", server.service.get_hostname(), ping)
+                    });
+                    for (i, option) in std::iter::once(host_text).chain(join_texts).enumerate() {
+                        let text = if i == menu_data.selected_option {
+                            format!("> {} <", option)
+                        } else {
+                            option
+                        };
+                        ui.label(menu_small_text(text));
+                    }
+                    if lan_state.servers.is_empty() {
+                        ui.add_space(10.0);
+                        ui.label(menu_tiny_text("Looking for games on the local network..."));
+                    }
+                }
+                MenuState::DirectConnectConfig => {
+                    let options = ["Host Game", "Join Game", "Spectate Game"];
+                    for (i, option) in options.iter().enumerate() {
+                        let text = if i == menu_data.selected_option {
+                            format!("> {} <", option)
+                        } else {
+                            option.to_string()
+                        };
+                        ui.label(menu_small_text(text));
+                    }
+                }
+                MenuState::DirectJoinConfig | MenuState::DirectSpectateConfig => {
+                    let label = if matches!(menu_data.state, MenuState::DirectSpectateConfig) {
+                        "Ticket of the Match to Spectate:"
+                    } else {
+                        "Connection Ticket:"
+                    };
+                    ui.label(menu_small_text(label));
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut menu_data.ticket_input)
+                            .desired_width(500.0)
+                            .font(egui::TextStyle::Monospace),
+                    );
+                    if network_state.status.is_idle() {
+                        response.request_focus();
+                    }
+                }
+                MenuState::MatchmakerSettings => {
+                    let settings = MatchmakerSettings::load(&storage);
+                    let default_option =
+                        format!("Default ({})", shorten_node_id(&meta.matchmaking_server));
+                    let options =
+                        std::iter::once((default_option, settings.selected.is_empty()))
+                            .chain(settings.servers.iter().map(|server| {
+                                (shorten_node_id(server), *server == settings.selected)
+                            }))
+                            .chain(std::iter::once(("Add Matchmaker".to_string(), false)));
+                    for (i, (option, in_use)) in options.enumerate() {
+                        let option = if in_use {
+                            format!("{} (in use)", option)
+                        } else {
+                            option
+                        };
+                        let text = if i == menu_data.selected_option {
+                            format!("> {} <", option)
+                        } else {
+                            option
+                        };
+                        ui.label(menu_small_text(text));
+                    }
+                    if cli_args.matchmaker.is_some() {
+                        ui.add_space(10.0);
+                        ui.label(menu_tiny_text(
+                            "Overridden by the --matchmaker command-line flag",
+                        ));
+                    }
+                }
+                MenuState::MatchmakerInput => {
+                    ui.label(menu_small_text("Matchmaker Node ID:"));
+                    ui.add(
+                        egui::TextEdit::singleline(&mut menu_data.matchmaker_input)
+                            .desired_width(500.0)
+                            .font(egui::TextStyle::Monospace),
+                    )
+                    .request_focus();
+                    if !menu_data.matchmaker_error.is_empty() {
+                        let text = RichText::new(&menu_data.matchmaker_error)
+                            .size(18.0)
+                            .color(Color32::LIGHT_RED)
+                            .strong();
+                        ui.label(text);
+                    }
+                }
+                MenuState::Replays => {
+                    // Only show the replays around the selected one
+                    let first_shown = menu_data
+                        .selected_option
+                        .saturating_sub(REPLAYS_SHOWN / 2)
+                        .min(replay_browser.files.len().saturating_sub(REPLAYS_SHOWN));
+                    for (i, path) in replay_browser
+                        .files
+                        .iter()
+                        .enumerate()
+                        .skip(first_shown)
+                        .take(REPLAYS_SHOWN)
+                    {
+                        let name = path
+                            .file_stem()
+                            .map(|name| name.to_string_lossy().to_string())
+                            .unwrap_or_default();
+                        let text = if i == menu_data.selected_option {
+                            format!("> {} <", name)
+                        } else {
+                            name
+                        };
+                        ui.label(menu_small_text(text));
+                    }
+                    if replay_browser.files.is_empty() {
+                        ui.label(menu_tiny_text("No replays saved yet"));
+                    }
+                    if !replay_browser.error.is_empty() {
+                        ui.add_space(10.0);
+                        let text = RichText::new(&replay_browser.error)
+                            .size(18.0)
+                            .color(Color32::LIGHT_RED)
+                            .strong();
+                        ui.label(text);
+                    }
+                }
+                MenuState::LocalPlayConfig => {
+                    let controls = if menu_data.local_use_gamepads {
+                        "Gamepad 1 / Gamepad 2"
+                    } else {
+                        "WASD + Space / Arrows + Right Shift"
+                    };
+                    ui.label(menu_small_text(format!("Controls: < {} >", controls)));
+                }
+                MenuState::SinglePlayerConfig => {
+                    let difficulty = meta
+                        .gameplay
+                        .bot_difficulties
+                        .get(menu_data.bot_difficulty_idx)
+                        .map(|difficulty| difficulty.name.as_str())
+                        .unwrap_or("None");
                     ui.label(menu_small_text(format!(
-                        "Input Delay Frames: {}",
-                        menu_data.input_delay_frames
+                        "CPU Difficulty: < {} >",
+                        difficulty
                     )));
                 }
             }
 
             ui.add_space(30.0);
-            match network_state.status {
-                NetworkGameStatus::Searching => {
-                    ui.label(menu_small_text("Waiting for an opponent.."));
-                }
+            match &network_state.status {
+                NetworkGameStatus::Searching => match network_state.retry_at {
+                    Some(retry_at) => {
+                        let retry_in = retry_at.saturating_duration_since(Instant::now());
+                        ui.label(menu_small_text("Couldn't reach the matchmaker"));
+                        if let Some(error) = &network_state.last_error {
+                            ui.label(menu_tiny_text(error));
+                        }
+                        ui.label(menu_tiny_text(format!(
+                            "Retrying in {}s (retry {} of {})",
+                            retry_in.as_secs() + 1,
+                            network_state.retries,
+                            MAX_MATCHMAKING_RETRIES
+                        )));
+                    }
+                    None => {
+                        ui.label(menu_small_text("Connecting to the matchmaker..."));
+                    }
+                },
                 NetworkGameStatus::WaitingForPlayers => {
-                    ui.label(menu_small_text("Waiting for an opponent..."));
+                    let text = match network_state.search_state {
+                        SearchState::Connecting => "Connecting to the matchmaker...",
+                        _ => "Waiting for an opponent...",
+                    };
+                    ui.label(menu_small_text(text));
+                    let elapsed = network_state.search_elapsed().as_secs();
+                    ui.label(menu_tiny_text(format!(
+                        "Searching for {}:{:02}",
+                        elapsed / 60,
+                        elapsed % 60
+                    )));
+                }
+                NetworkGameStatus::Hosting => {
+                    ui.label(menu_small_text("Hosting, waiting for a player to join..."));
+                    if let Some(mut ticket) = lan_state.host_ticket() {
+                        ui.add_space(10.0);
+                        ui.label(menu_tiny_text("Share your connection ticket:"));
+                        // A read-only text field, so the ticket can be selected and copied
+                        ui.add(
+                            egui::TextEdit::singleline(&mut ticket)
+                                .desired_width(500.0)
+                                .font(egui::TextStyle::Monospace),
+                        );
+                    }
+                }
+                NetworkGameStatus::Joining
+                    if matches!(menu_data.state, MenuState::DirectJoinConfig) =>
+                {
+                    ui.label(menu_small_text("Connecting to the host..."));
+                }
+                NetworkGameStatus::Joining
+                    if matches!(menu_data.state, MenuState::DirectSpectateConfig) =>
+                {
+                    ui.label(menu_small_text("Connecting to the match..."));
+                }
+                NetworkGameStatus::Joining => {
+                    let host = lan_state
+                        .joined_server
+                        .as_ref()
+                        .map(|server| server.service.get_hostname().to_string())
+                        .unwrap_or_default();
+                    ui.label(menu_small_text(format!(
+                        "Joined {}, waiting for the host...",
+                        host
+                    )));
                 }
                 NetworkGameStatus::MatchFound => {
                     ui.label(menu_small_text("Match Starting..."));
+                    let ping = match network_state.handshake.rtt() {
+                        Some(rtt) => format!(
+                            "Ping: {}ms, recommended input delay: {}",
+                            rtt.as_millis(),
+                            recommended_input_delay(rtt)
+                        ),
+                        None => "Measuring ping...".to_string(),
+                    };
+                    ui.label(menu_tiny_text(ping));
+                }
+                NetworkGameStatus::Spectating => {
+                    ui.label(menu_small_text("Spectating, waiting for the match..."));
+                }
+                NetworkGameStatus::Error(reason) => {
+                    let text = RichText::new(reason)
+                        .size(22.0)
+                        .color(Color32::LIGHT_RED)
+                        .strong();
+                    ui.label(text);
+                    ui.label(menu_tiny_text("Press Enter to continue"));
                 }
                 NetworkGameStatus::Idle => {}
             }
@@ -178,6 +752,37 @@ fn menu_draw_system(
 
             if matches!(menu_data.state, MenuState::OnlinePlayConfig) {
                 ui.label(menu_tiny_text("Press Enter to start matchmaking..."));
+            } else if matches!(menu_data.state, MenuState::LanPlayConfig) {
+                ui.label(menu_tiny_text("Press Enter to host or join a game..."));
+            } else if matches!(menu_data.state, MenuState::DirectConnectConfig) {
+                ui.label(menu_tiny_text(
+                    "Press Enter to host, join or spectate a game...",
+                ));
+            } else if matches!(menu_data.state, MenuState::MatchmakerSettings) {
+                ui.label(menu_tiny_text(
+                    "Press Enter to use the selected matchmaker...",
+                ));
+            } else if matches!(menu_data.state, MenuState::Replays) {
+                ui.label(menu_tiny_text(
+                    "Press Enter to watch the selected replay...",
+                ));
+            } else if matches!(menu_data.state, MenuState::MatchmakerInput) {
+                ui.label(menu_tiny_text(
+                    "Paste the node ID and press Enter to save...",
+                ));
+            } else if matches!(menu_data.state, MenuState::DirectJoinConfig) {
+                ui.label(menu_tiny_text(
+                    "Paste the host's ticket and press Enter to join...",
+                ));
+            } else if matches!(menu_data.state, MenuState::DirectSpectateConfig) {
+                ui.label(menu_tiny_text(
+                    "Paste the spectator ticket and press Enter to watch...",
+                ));
+            } else if matches!(
+                menu_data.state,
+                MenuState::LocalPlayConfig | MenuState::SinglePlayerConfig
+            ) {
+                ui.label(menu_tiny_text("Press Enter to start a local match..."));
             }
         });
     });
@@ -192,7 +797,6 @@ fn menu_startup(
     **clear_color = Color::BLACK;
     egui_settings.scale = 2.0;
     menu_data.scroll_timer = Duration::ZERO;
-    menu_data.input_delay_frames = 2;
 }
 
 /// Handles the menu input by interacting with the input collector directly
@@ -222,3 +826,11 @@ fn menu_tiny_text(text: impl Into<String>) -> RichText {
         .color(Color32::WHITE)
         .strong()
 }
+
+/// Shortens a node ID for display, they are too long to fit in the menu
+fn shorten_node_id(node_id: &str) -> String {
+    match node_id.get(..12) {
+        Some(prefix) if node_id.len() > 12 => format!("{}...", prefix),
+        _ => node_id.to_string(),
+    }
+}
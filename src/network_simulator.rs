@@ -0,0 +1,218 @@
+use crate::gameplay::NetworkingDebugMenuState;
+use bones_framework::networking::ggrs::{Message, NonBlockingSocket};
+use bones_framework::networking::BoxedNonBlockingSocket;
+use bones_framework::prelude::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The extra delay of reordered messages, so the messages sent after them arrive first
+pub const REORDER_DELAY: Duration = Duration::from_millis(40);
+/// The seed of the pseudo random generator, identical every match for reproducible runs
+const RNG_SEED: u32 = 0x2545_f491;
+/// How often the delayed messages are checked, independently of GGRS polling the socket
+const FLUSH_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The network conditions simulated on the outgoing GGRS messages
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// Delay added to every message, in milliseconds
+    pub latency_ms: u32,
+    /// Maximum random variation of the delay in either direction, in milliseconds
+    pub jitter_ms: u32,
+    /// Chance of a message being dropped, between 0 and 1
+    pub packet_loss: f32,
+    /// Chance of a message being held back so later messages overtake it, between 0 and 1
+    pub reorder: f32,
+}
+
+impl NetworkConditions {
+    /// Returns true if no condition is simulated
+    pub fn is_perfect(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Shared resource holding the simulated network conditions. The conditions are shared with the
+/// sockets of running matches, so changing them from the debug window applies immediately.
+#[derive(HasSchema, Clone, Default)]
+pub struct NetworkSimulator {
+    conditions: Arc<Mutex<NetworkConditions>>,
+}
+
+impl NetworkSimulator {
+    /// Creates a new NetworkSimulator with the given initial conditions
+    pub fn new(conditions: NetworkConditions) -> Self {
+        Self {
+            conditions: Arc::new(Mutex::new(conditions)),
+        }
+    }
+
+    /// Returns the current conditions
+    pub fn conditions(&self) -> NetworkConditions {
+        self.conditions.lock().unwrap().clone()
+    }
+
+    /// Replaces the current conditions
+    pub fn set_conditions(&self, conditions: NetworkConditions) {
+        *self.conditions.lock().unwrap() = conditions;
+    }
+
+    /// Wraps the GGRS socket of a match so the simulated conditions apply to it. A background
+    /// thread sends the delayed messages once they are due, until the socket is dropped.
+    pub fn wrap(&self, socket: BoxedNonBlockingSocket) -> BoxedNonBlockingSocket {
+        let socket = Arc::new(Mutex::new(NetworkConditionSocket {
+            inner: socket,
+            queue: DelayQueue::new(self.conditions.clone()),
+        }));
+        let flushed_socket = Arc::downgrade(&socket);
+        std::thread::spawn(move || {
+            while let Some(socket) = flushed_socket.upgrade() {
+                socket.lock().unwrap().flush(Instant::now());
+                drop(socket);
+                std::thread::sleep(FLUSH_INTERVAL);
+            }
+        });
+        BoxedNonBlockingSocket(Box::new(SharedSocket(socket)))
+    }
+}
+
+/// Messages delayed, dropped and reordered according to the network conditions until they are
+/// due. Generic over the message, GGRS messages can only be created by GGRS itself.
+pub struct DelayQueue<T> {
+    conditions: Arc<Mutex<NetworkConditions>>,
+    /// Messages waiting to be sent, with the time they are due
+    queue: VecDeque<(Instant, T)>,
+    /// State of the pseudo random generator
+    rng_state: u32,
+}
+
+impl<T> DelayQueue<T> {
+    /// Creates an empty queue applying the given conditions
+    pub fn new(conditions: Arc<Mutex<NetworkConditions>>) -> Self {
+        Self {
+            conditions,
+            queue: VecDeque::new(),
+            rng_state: RNG_SEED,
+        }
+    }
+
+    /// Returns true if no message is waiting
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns a pseudo random value between 0 and 1
+    fn random(&mut self) -> f32 {
+        // xorshift32
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 17;
+        self.rng_state ^= self.rng_state << 5;
+        (self.rng_state >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Queues a message sent at `now`, or drops it
+    pub fn push(&mut self, message: T, now: Instant) {
+        let conditions = self.conditions.lock().unwrap().clone();
+        if self.random() < conditions.packet_loss {
+            return;
+        }
+        let jitter = (self.random() * 2.0 - 1.0) * conditions.jitter_ms as f32;
+        let delay_ms = (conditions.latency_ms as f32 + jitter).max(0.0);
+        let mut delay = Duration::from_secs_f32(delay_ms / 1000.0);
+        if self.random() < conditions.reorder {
+            delay += REORDER_DELAY;
+        }
+        self.queue.push_back((now + delay, message));
+    }
+
+    /// Removes the messages which are due at `now`, in the order they are due
+    pub fn pop_due(&mut self, now: Instant) -> Vec<T> {
+        let mut due = Vec::new();
+        let mut idx = 0;
+        while idx < self.queue.len() {
+            if self.queue[idx].0 <= now {
+                due.push(self.queue.remove(idx).unwrap());
+            } else {
+                idx += 1;
+            }
+        }
+        // Stable, so messages due at the same time keep the order they were sent in
+        due.sort_by_key(|(due_at, _)| *due_at);
+        due.into_iter().map(|(_, message)| message).collect()
+    }
+}
+
+/// A GGRS socket shim which delays, drops and reorders the messages sent through the inner socket.
+///
+/// The conditions are applied to outgoing messages only, so running two clients with the same
+/// conditions simulates them in both directions.
+struct NetworkConditionSocket {
+    inner: BoxedNonBlockingSocket,
+    queue: DelayQueue<(usize, Message)>,
+}
+
+impl NetworkConditionSocket {
+    /// Sends the queued messages which are due
+    fn flush(&mut self, now: Instant) {
+        for (addr, message) in self.queue.pop_due(now) {
+            self.inner.send_to(&message, &addr);
+        }
+    }
+}
+
+/// The NetworkConditionSocket handed to GGRS, shared with the thread flushing it
+struct SharedSocket(Arc<Mutex<NetworkConditionSocket>>);
+
+impl NonBlockingSocket<usize> for SharedSocket {
+    fn send_to(&mut self, msg: &Message, addr: &usize) {
+        let mut socket = self.0.lock().unwrap();
+        let perfect = socket.queue.conditions.lock().unwrap().is_perfect();
+        if perfect && socket.queue.is_empty() {
+            socket.inner.send_to(msg, addr);
+            return;
+        }
+
+        let now = Instant::now();
+        socket.queue.push((*addr, msg.clone()), now);
+        socket.flush(now);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(usize, Message)> {
+        let mut socket = self.0.lock().unwrap();
+        socket.flush(Instant::now());
+        socket.inner.receive_all_messages()
+    }
+}
+
+/// Draws the window to change the simulated network conditions along with the networking debug
+/// menu (F1)
+pub fn network_simulator_window(
+    network_simulator: Res<NetworkSimulator>,
+    debug_menu_state: Res<NetworkingDebugMenuState>,
+    ctx: Res<EguiCtx>,
+) {
+    if !debug_menu_state.detailed_menu_open {
+        return;
+    }
+
+    let mut conditions = network_simulator.conditions();
+    let mut packet_loss_percent = conditions.packet_loss * 100.0;
+    let mut reorder_percent = conditions.reorder * 100.0;
+    egui::Window::new("Network Conditions")
+        .default_pos((10.0, 300.0))
+        .show(&ctx, |ui| {
+            ui.add(egui::Slider::new(&mut conditions.latency_ms, 0..=500).text("Latency (ms)"));
+            ui.add(egui::Slider::new(&mut conditions.jitter_ms, 0..=200).text("Jitter (ms)"));
+            ui.add(egui::Slider::new(&mut packet_loss_percent, 0.0..=50.0).text("Packet loss (%)"));
+            ui.add(egui::Slider::new(&mut reorder_percent, 0.0..=50.0).text("Reorder (%)"));
+            if ui.button("Reset").clicked() {
+                conditions = default();
+                packet_loss_percent = 0.0;
+                reorder_percent = 0.0;
+            }
+        });
+    conditions.packet_loss = packet_loss_percent / 100.0;
+    conditions.reorder = reorder_percent / 100.0;
+    network_simulator.set_conditions(conditions);
+}
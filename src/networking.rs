@@ -1,27 +1,214 @@
-use crate::gameplay::GameplayPlugin;
-use crate::input::GameNetworkInputConfig;
-use crate::menu::menu::MenuData;
-use crate::GameMeta;
-use bones_framework::networking::online::{self, SearchState};
+use crate::cli::CliArgs;
+use crate::gameplay::{
+    ConnectionMonitor, GameplayPlugin, GameplaySprites, GgrsConnection, MatchRules, PhysicsMeta,
+    PlayerNames, StateChecksum,
+};
+use crate::input::{DensePlayerControl, GameNetworkInputConfig};
+use crate::menu::menu::{MenuData, MenuState};
+use crate::network_simulator::NetworkSimulator;
+use crate::settings::MatchmakerSettings;
+use crate::{GameMeta, SessionNames};
+use bones_framework::networking::lan;
+use bones_framework::networking::online::{self, OnlineMatchmakerResponse, SearchState};
 use bones_framework::networking::GgrsSessionRunner;
 use bones_framework::networking::GgrsSessionRunnerInfo;
+use bones_framework::networking::{NetworkMatchSocket, SocketTarget};
 use bones_framework::prelude::*;
+use mdns_sd::ServiceInfo;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// The target frames per second for the game
-const FPS: f32 = 60.0;
+pub const FPS: f32 = 60.0;
 /// The maximum number of frames the game can predict ahead
-const MAX_PREDICTION_WINDOW: Option<usize> = Some(10);
+pub const MAX_PREDICTION_WINDOW: Option<usize> = Some(10);
 /// The maximum number of players allowed in a game
 const MAX_PLAYERS: u32 = 2;
+/// The mDNS service type LAN games are advertised and discovered under
+const LAN_SERVICE_TYPE: &str = "bones_volleyball";
+/// The mDNS service type spectators join a running match under
+pub const SPECTATE_SERVICE_TYPE: &str = "bones_volleyball_spectate";
+/// The service property bones publishes the node address of a hosted game under: the hex encoded
+/// node ID, relay URL and direct addresses the host is reachable at
+pub const NODE_ADDR_PROPERTY: &str = "node-addr";
+/// How long joining a game by ticket waits for the host before giving up
+const JOIN_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long an attempt to reach the matchmaker may take before it is retried
+const MATCHMAKER_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The number of times reaching the matchmaker is retried before giving up
+pub const MAX_MATCHMAKING_RETRIES: u32 = 3;
+/// The delay before the first retry, doubled after every failed attempt
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+/// The input delay setting which picks the delay from the ping measured before the match
+pub const AUTO_INPUT_DELAY: usize = 0;
+/// The largest input delay which can be picked
+pub const MAX_INPUT_DELAY: usize = 60;
+/// The number of round trips measured during the match-start handshake
+const PING_SAMPLES: usize = 5;
+/// How long the match-start handshake waits for the other player before giving up
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages exchanged through the reliable channel of the match socket, outside of the GGRS inputs
+#[derive(Clone, Debug)]
+pub enum ReliableMessage {
+    /// The physics config and match rules of the sender, exchanged during the match-start handshake
+    MatchConfig(PhysicsMeta, MatchRules),
+    /// The checksum of a confirmed frame, used to detect desyncs
+    StateChecksum(StateChecksum),
+    /// The sender chose to leave the match
+    LeftMatch,
+    /// Sent during the match-start handshake to measure the round trip time
+    Ping(u32),
+    /// The answer to the ping with the same id
+    Pong(u32),
+    /// The input delay recommended by the sender from its measured round trip time
+    InputDelay(u32),
+    /// The name of the sender, exchanged during the match-start handshake
+    PlayerName(String),
+    /// The names of both players by player index, sent to spectators
+    PlayerNames([String; 2]),
+    /// The inputs of both players for consecutive confirmed frames starting at the given frame,
+    /// streamed to spectators
+    ConfirmedInputs(u32, Vec<[DensePlayerControl; 2]>),
+}
+
+impl ReliableMessage {
+    /// Serializes the message, the first byte identifies the message type
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            ReliableMessage::MatchConfig(physics, rules) => {
+                let mut bytes = vec![0];
+                bytes.extend(physics.to_bytes());
+                bytes.extend(rules.to_bytes());
+                bytes
+            }
+            ReliableMessage::StateChecksum(entry) => {
+                let mut bytes = vec![1];
+                bytes.extend_from_slice(&entry.frame.to_le_bytes());
+                bytes.extend_from_slice(&entry.checksum.to_le_bytes());
+                bytes.extend_from_slice(entry.description.as_bytes());
+                bytes
+            }
+            ReliableMessage::LeftMatch => vec![2],
+            ReliableMessage::Ping(id) => [&[3], &id.to_le_bytes()[..]].concat(),
+            ReliableMessage::Pong(id) => [&[4], &id.to_le_bytes()[..]].concat(),
+            ReliableMessage::InputDelay(frames) => [&[5], &frames.to_le_bytes()[..]].concat(),
+            ReliableMessage::PlayerName(name) => [&[6], name.as_bytes()].concat(),
+            ReliableMessage::PlayerNames(names) => {
+                let mut bytes = vec![7];
+                for name in names {
+                    let name = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+                    bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                    bytes.extend_from_slice(name);
+                }
+                bytes
+            }
+            ReliableMessage::ConfirmedInputs(first_frame, inputs) => {
+                let mut bytes = vec![8];
+                bytes.extend_from_slice(&first_frame.to_le_bytes());
+                for input in inputs.iter().flatten() {
+                    bytes.extend_from_slice(&input.bits().to_le_bytes());
+                }
+                bytes
+            }
+        }
+    }
+
+    /// Deserializes a message, returns None for unknown or malformed messages
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (message_type, payload) = bytes.split_first()?;
+        match message_type {
+            0 if payload.len() >= PhysicsMeta::BYTES_LEN => {
+                let (physics, rules) = payload.split_at(PhysicsMeta::BYTES_LEN);
+                Some(ReliableMessage::MatchConfig(
+                    PhysicsMeta::from_bytes(physics)?,
+                    MatchRules::from_bytes(rules)?,
+                ))
+            }
+            1 if payload.len() >= 12 => Some(ReliableMessage::StateChecksum(StateChecksum {
+                frame: u32::from_le_bytes(payload[0..4].try_into().ok()?),
+                checksum: u64::from_le_bytes(payload[4..12].try_into().ok()?),
+                description: String::from_utf8_lossy(&payload[12..]).into_owned(),
+            })),
+            2 => Some(ReliableMessage::LeftMatch),
+            3 => Some(ReliableMessage::Ping(u32::from_le_bytes(
+                payload.get(0..4)?.try_into().ok()?,
+            ))),
+            4 => Some(ReliableMessage::Pong(u32::from_le_bytes(
+                payload.get(0..4)?.try_into().ok()?,
+            ))),
+            5 => Some(ReliableMessage::InputDelay(u32::from_le_bytes(
+                payload.get(0..4)?.try_into().ok()?,
+            ))),
+            6 => Some(ReliableMessage::PlayerName(
+                String::from_utf8_lossy(payload).into_owned(),
+            )),
+            7 => {
+                let mut rest = payload;
+                let mut read_name = || {
+                    let len = u16::from_le_bytes(rest.get(0..2)?.try_into().ok()?) as usize;
+                    let name = String::from_utf8_lossy(rest.get(2..2 + len)?).into_owned();
+                    rest = &rest[2 + len..];
+                    Some(name)
+                };
+                Some(ReliableMessage::PlayerNames([read_name()?, read_name()?]))
+            }
+            8 if payload.len() >= 4 && (payload.len() - 4) % 8 == 0 => {
+                let first_frame = u32::from_le_bytes(payload[0..4].try_into().ok()?);
+                let inputs = payload[4..]
+                    .chunks_exact(8)
+                    .map(|chunk| {
+                        [&chunk[0..4], &chunk[4..8]].map(|bits| {
+                            DensePlayerControl::from_bits(u32::from_le_bytes(
+                                bits.try_into().unwrap(),
+                            ))
+                        })
+                    })
+                    .collect();
+                Some(ReliableMessage::ConfirmedInputs(first_frame, inputs))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Resource holding the socket of an online match in the gameplay_ui session, along with the
+/// reliable messages received during the current frame. None for local matches.
+#[derive(HasSchema, Clone, Default)]
+pub struct MatchSocket {
+    pub socket: Option<NetworkMatchSocket>,
+    pub messages: Vec<ReliableMessage>,
+}
+
+/// Receives the reliable messages of this frame, so that every system can look at them
+pub fn receive_reliable_messages(mut match_socket: ResMut<MatchSocket>) {
+    let messages = match &match_socket.socket {
+        Some(socket) => socket
+            .recv_reliable()
+            .into_iter()
+            .filter_map(|(_, bytes)| ReliableMessage::from_bytes(&bytes))
+            .collect(),
+        None => Vec::new(),
+    };
+    match_socket.messages = messages;
+}
 
 /// Represents the current status of the network game
-#[derive(HasSchema, Default, PartialEq, Eq, Clone, Copy)]
+#[derive(HasSchema, Default, PartialEq, Eq, Clone)]
 pub enum NetworkGameStatus {
     #[default]
     Idle,
     Searching,
     WaitingForPlayers,
+    /// Hosting a game, waiting for another player to join from the LAN or by ticket
+    Hosting,
+    /// Joined a hosted game, waiting for the host to start it
+    Joining,
     MatchFound,
+    /// Connected to a running match as a spectator, waiting for its config
+    Spectating,
+    /// Connecting to the matchmaker or another player failed for the given reason
+    Error(String),
 }
 
 impl NetworkGameStatus {
@@ -44,14 +231,34 @@ impl NetworkGameStatus {
     pub fn is_waiting_for_players(&self) -> bool {
         matches!(self, NetworkGameStatus::WaitingForPlayers)
     }
+
+    /// Returns true if the status is Error
+    pub fn is_error(&self) -> bool {
+        matches!(self, NetworkGameStatus::Error(_))
+    }
 }
 
 /// Represents the current state of the network game
 #[derive(HasSchema, Clone)]
-#[repr(C)]
 pub struct NetworkGameState {
     /// The current status of the network game
     pub status: NetworkGameStatus,
+    /// The socket of the found match, held while performing the match-start handshake
+    pub socket: Option<NetworkMatchSocket>,
+    /// The progress of the current search, as reported by the matchmaker
+    pub search_state: SearchState,
+    /// When the player started searching for a match, kept across retries
+    pub search_started: Option<Instant>,
+    /// When the current attempt to reach the matchmaker started
+    attempt_started: Option<Instant>,
+    /// The number of failed attempts to reach the matchmaker during this search
+    pub retries: u32,
+    /// When the next attempt starts after a failed one
+    pub retry_at: Option<Instant>,
+    /// Why the last attempt to reach the matchmaker failed
+    pub last_error: Option<String>,
+    /// The progress of the match-start handshake once a match was found
+    pub handshake: MatchHandshake,
 }
 
 impl Default for NetworkGameState {
@@ -66,65 +273,563 @@ impl NetworkGameState {
     pub fn new() -> Self {
         Self {
             status: NetworkGameStatus::Idle,
+            socket: None,
+            search_state: SearchState::Connecting,
+            search_started: None,
+            attempt_started: None,
+            retries: 0,
+            retry_at: None,
+            last_error: None,
+            handshake: MatchHandshake::default(),
         }
     }
 
     /// Resets the network game state to idle
     pub fn reset(&mut self) {
-        self.status = NetworkGameStatus::Idle;
+        *self = Self::new();
+    }
+
+    /// Stops searching for a match if a search is running, then resets to idle
+    pub fn cancel(&mut self) {
+        if self.status.is_waiting_for_players() {
+            let _ = online::stop_search_for_game();
+        }
+        self.reset();
+    }
+
+    /// Reports a connection failure, shown in the menu until dismissed
+    pub fn fail(&mut self, reason: impl Into<String>) {
+        let reason = reason.into();
+        println!("Connection failed: {}", reason);
+        self.reset();
+        self.status = NetworkGameStatus::Error(reason);
+    }
+
+    /// Returns how long the player has been searching for a match
+    pub fn search_elapsed(&self) -> Duration {
+        self.search_started
+            .map(|search_started| search_started.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Gives up on the current attempt to reach the matchmaker because of the given error. It is
+    /// retried with an exponential backoff, until MAX_MATCHMAKING_RETRIES retries failed.
+    fn retry_search(&mut self, error: String) {
+        let _ = online::stop_search_for_game();
+        if self.retries >= MAX_MATCHMAKING_RETRIES {
+            self.fail(format!(
+                "Couldn't reach the matchmaker after {} attempts: {}",
+                self.retries + 1,
+                error
+            ));
+            return;
+        }
+
+        let backoff = RETRY_BACKOFF * 2u32.pow(self.retries);
+        println!("Matchmaker error: {}, retrying in {:?}", error, backoff);
+        self.retries += 1;
+        self.retry_at = Some(Instant::now() + backoff);
+        self.last_error = Some(error);
+        self.status = NetworkGameStatus::Searching;
+    }
+
+    /// Stores the socket of a found match and starts the handshake by sending our physics config,
+    /// rules and name to the other player
+    fn start_handshake(&mut self, socket: NetworkMatchSocket, meta: &GameMeta) {
+        let message = ReliableMessage::MatchConfig(
+            meta.gameplay.physics.clone(),
+            meta.gameplay.rules.clone(),
+        );
+        socket.send_reliable(SocketTarget::All, &message.to_bytes());
+        let message = ReliableMessage::PlayerName(lan_host_name());
+        socket.send_reliable(SocketTarget::All, &message.to_bytes());
+        self.status = NetworkGameStatus::MatchFound;
+        self.socket = Some(socket);
+    }
+
+    /// Stores the socket connected to the host of a running match, which sends its config and
+    /// inputs to spectators without a handshake
+    fn start_spectating(&mut self, socket: NetworkMatchSocket) {
+        self.status = NetworkGameStatus::Spectating;
+        self.socket = Some(socket);
+    }
+}
+
+/// Progress of the match-start handshake, in which both players exchange their config and measure
+/// the round trip time to recommend an input delay
+#[derive(Clone, Default)]
+pub struct MatchHandshake {
+    /// When the handshake started
+    started: Option<Instant>,
+    /// The other player's physics config and match rules
+    remote_config: Option<(PhysicsMeta, MatchRules)>,
+    /// The id of the ping waiting for its pong, with the time it was sent
+    pending_ping: Option<(u32, Instant)>,
+    /// The round trip times measured so far
+    rtt_samples: Vec<Duration>,
+    /// The input delay recommended by the other player
+    remote_input_delay: Option<usize>,
+    /// The name of the other player
+    remote_name: Option<String>,
+}
+
+impl MatchHandshake {
+    /// Returns the median of the measured round trip times, once all of them were measured
+    pub fn rtt(&self) -> Option<Duration> {
+        if self.rtt_samples.len() < PING_SAMPLES {
+            return None;
+        }
+        let mut samples = self.rtt_samples.clone();
+        samples.sort();
+        Some(samples[samples.len() / 2])
+    }
+
+    /// Handles a message received during the handshake
+    fn receive(&mut self, message: ReliableMessage, socket: &NetworkMatchSocket) {
+        match message {
+            ReliableMessage::MatchConfig(physics, rules) => {
+                self.remote_config = Some((physics, rules));
+            }
+            ReliableMessage::Ping(id) => {
+                socket.send_reliable(SocketTarget::All, &ReliableMessage::Pong(id).to_bytes());
+            }
+            ReliableMessage::Pong(id) => {
+                let Some((pending_id, sent_at)) = self.pending_ping else {
+                    return;
+                };
+                if pending_id != id {
+                    return;
+                }
+                self.pending_ping = None;
+                self.rtt_samples.push(sent_at.elapsed());
+
+                // Share our recommendation once all round trips were measured
+                if let Some(rtt) = self.rtt() {
+                    let frames = recommended_input_delay(rtt) as u32;
+                    let message = ReliableMessage::InputDelay(frames);
+                    socket.send_reliable(SocketTarget::All, &message.to_bytes());
+                }
+            }
+            ReliableMessage::InputDelay(frames) => {
+                self.remote_input_delay = Some(frames as usize);
+            }
+            ReliableMessage::PlayerName(name) => {
+                self.remote_name = Some(name);
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends the next ping, one at a time until all round trips were measured
+    fn send_ping(&mut self, socket: &NetworkMatchSocket) {
+        if self.pending_ping.is_some() || self.rtt_samples.len() >= PING_SAMPLES {
+            return;
+        }
+        let id = self.rtt_samples.len() as u32;
+        socket.send_reliable(SocketTarget::All, &ReliableMessage::Ping(id).to_bytes());
+        self.pending_ping = Some((id, Instant::now()));
+    }
+}
+
+/// Polls the matchmaker like `online::update_search_for_game`, which only logs the matchmaker's
+/// errors, but returns them so the search can be retried and the reason shown in the menu
+fn poll_matchmaker(search_state: &mut SearchState) -> Result<Option<NetworkMatchSocket>, String> {
+    while let Ok(response) = online::ONLINE_MATCHMAKER.try_recv() {
+        match response {
+            OnlineMatchmakerResponse::Searching => *search_state = SearchState::Searching,
+            OnlineMatchmakerResponse::PlayerCount(count) => {
+                *search_state = SearchState::WaitingForPlayers(count);
+            }
+            OnlineMatchmakerResponse::GameStarting { socket, .. } => {
+                *search_state = SearchState::Connecting;
+                return Ok(Some(NetworkMatchSocket(Arc::new(socket))));
+            }
+            OnlineMatchmakerResponse::Error(error) => return Err(error),
+            // Lobbies aren't used
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the input delay which hides the given round trip time, i.e. the number of frames it
+/// takes an input to reach the other player
+pub fn recommended_input_delay(rtt: Duration) -> usize {
+    let frames = (rtt.as_millis() as u64 * FPS as u64).div_ceil(2000) as usize;
+    frames.clamp(1, MAX_INPUT_DELAY)
+}
+
+/// Returns whether the text is a valid node ID, the z-base-32 encoding of a 32 byte public key
+pub fn is_valid_node_id(text: &str) -> bool {
+    const Z_BASE_32: &str = "ybndrfg8ejkmcpqxot1uwisza345h769";
+    text.len() == 52 && text.chars().all(|c| Z_BASE_32.contains(c))
+}
+
+/// Parses a node ID typed by the player or passed on the command line, ignoring surrounding
+/// whitespace. Both the matchmaker field of the menu (parsing into a `String`) and the search for
+/// a match go through here, so they accept the same IDs.
+pub fn parse_node_id<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    let text = text.trim();
+    if !is_valid_node_id(text) {
+        return Err(format!(
+            "\"{}\" is not a valid node ID, expected 52 lowercase z-base-32 characters",
+            text
+        ));
+    }
+    text.parse()
+        .map_err(|_| format!("\"{}\" is not a valid node ID", text))
+}
+
+/// Returns whether the text looks like a connection ticket, the hex encoded node address of a host
+pub fn is_valid_ticket(text: &str) -> bool {
+    !text.is_empty() && text.len() % 2 == 0 && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Returns the server to join for a connection ticket. `lan::join_server` dials the node address
+/// in the ticket directly, so the service carries no IP addresses of its own.
+pub fn ticket_server_info(service_type: &str, ticket: &str) -> Result<lan::ServerInfo, String> {
+    let service = ServiceInfo::new(
+        &format!("_{}._udp.local.", service_type),
+        "direct",
+        "direct.local.",
+        (),
+        0,
+        &[(NODE_ADDR_PROPERTY, ticket)][..],
+    )
+    .map_err(|err| format!("Invalid connection ticket: {}", err))?;
+    Ok(lan::ServerInfo {
+        service,
+        ping: None,
+    })
+}
+
+/// State of hosting and joining games without the matchmaker, either discovered on the local
+/// network or directly through the host's node address (its connection ticket). Lives in the
+/// menu session.
+#[derive(HasSchema, Clone, Default)]
+pub struct LanGameState {
+    /// The LAN games discovered so far
+    pub servers: Vec<lan::ServerInfo>,
+    /// Receives the mDNS discovery events while browsing for games
+    discovery: Option<lan::ServiceDiscoveryReceiver>,
+    /// The server advertised while hosting
+    host_info: Option<lan::ServerInfo>,
+    /// Whether the hosted server has been started
+    hosting: bool,
+    /// The number of players in the hosted game, including the host
+    joined_players: usize,
+    /// The server joined as a client
+    pub joined_server: Option<lan::ServerInfo>,
+    /// When the joined server was joined by ticket, to give up if the host can't be reached
+    join_started: Option<Instant>,
+    /// Whether the joined server is a running match joined as a spectator
+    pub spectating: bool,
+}
+
+impl LanGameState {
+    /// Joins the discovered server with the given index, explains why if it can't be joined
+    pub fn join(&mut self, server_idx: usize) -> Result<(), String> {
+        let server = self
+            .servers
+            .get(server_idx)
+            .cloned()
+            .ok_or("The game is no longer available")?;
+        lan::join_server(&server)
+            .map_err(|err| format!("Failed to join {}: {}", server.service.get_hostname(), err))?;
+        self.joined_server = Some(server);
+        Ok(())
+    }
+
+    /// Returns the connection ticket of the hosted game, for the other player to join it directly
+    pub fn host_ticket(&self) -> Option<&str> {
+        self.host_info
+            .as_ref()
+            .filter(|_| self.hosting)?
+            .service
+            .get_property_val_str(NODE_ADDR_PROPERTY)
+    }
+
+    /// Joins the game hosted by the node with the given ticket, on the local network or not. When
+    /// spectating, the running match of the node is joined instead.
+    pub fn join_ticket(&mut self, ticket: &str, spectate: bool) -> Result<(), String> {
+        let ticket = ticket.trim();
+        if !is_valid_ticket(ticket) {
+            return Err("Not a valid connection ticket".to_string());
+        }
+
+        let service_type = if spectate {
+            SPECTATE_SERVICE_TYPE
+        } else {
+            LAN_SERVICE_TYPE
+        };
+        let server = ticket_server_info(service_type, ticket)?;
+        lan::join_server(&server).map_err(|err| format!("Failed to reach the host: {}", err))?;
+        self.joined_server = Some(server);
+        self.join_started = Some(Instant::now());
+        self.spectating = spectate;
+        Ok(())
+    }
+
+    /// Stops hosting or leaves the joined server
+    fn cancel(&mut self) {
+        if let Some(server) = self.host_info.as_ref().filter(|_| self.hosting) {
+            lan::stop_server(server);
+        }
+        if let Some(server) = self.joined_server.take() {
+            lan::leave_server(&server);
+        }
+        self.hosting = false;
+        self.joined_players = 0;
+        self.join_started = None;
+        self.spectating = false;
+    }
+}
+
+/// Returns the name this machine's LAN game is advertised under, also shown as the player's name
+pub fn lan_host_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "Player".to_string())
+}
+
+/// Handles hosting, discovering and joining games on the local network or by ticket, without the
+/// matchmaker. Once both players are connected the regular match-start handshake takes over.
+pub fn handle_lan_menu_matchmaking(
+    mut network_state: ResMut<NetworkGameState>,
+    mut lan_state: ResMut<LanGameState>,
+    menu_data: Res<MenuData>,
+    meta: Root<GameMeta>,
+) {
+    match network_state.status {
+        NetworkGameStatus::Hosting => {
+            let LanGameState {
+                host_info,
+                hosting,
+                joined_players,
+                ..
+            } = &mut *lan_state;
+            let (_, server) = lan::prepare_to_host(host_info, LAN_SERVICE_TYPE, &lan_host_name());
+            if !*hosting {
+                println!("Hosting a game as {}", server.service.get_hostname());
+                lan::start_server(server.clone(), MAX_PLAYERS);
+                *hosting = true;
+            }
+            if let Some(lan_socket) = lan::wait_players(joined_players, server) {
+                println!("Player joined the hosted game!");
+                *hosting = false;
+                network_state.start_handshake(lan_socket, &meta);
+            }
+        }
+        NetworkGameStatus::Joining => {
+            if let Some(lan_socket) = lan::wait_game_start() {
+                println!("Hosted game started!");
+                lan_state.joined_server = None;
+                lan_state.join_started = None;
+                if std::mem::take(&mut lan_state.spectating) {
+                    network_state.start_spectating(lan_socket);
+                } else {
+                    network_state.start_handshake(lan_socket, &meta);
+                }
+            } else if lan_state
+                .join_started
+                .is_some_and(|join_started| join_started.elapsed() >= JOIN_TIMEOUT)
+            {
+                lan_state.cancel();
+                network_state.fail("Couldn't reach the host, check the connection ticket");
+            }
+        }
+        NetworkGameStatus::Idle => {
+            lan_state.cancel();
+
+            // Keep the list of games up to date while the LAN menu is open
+            if matches!(menu_data.state, MenuState::LanPlayConfig) {
+                let LanGameState {
+                    servers, discovery, ..
+                } = &mut *lan_state;
+                lan::prepare_to_join(LAN_SERVICE_TYPE, servers, discovery, &|| {});
+            }
+        }
+        _ => {}
     }
 }
 
 /// Handles the matchmaking/connection logic tied to the online menu state by matching on NetworkGameStatus
 pub fn handle_online_menu_matchmaking(
     mut network_state: ResMut<NetworkGameState>,
-    sessions: ResMut<Sessions>,
+    mut sessions: ResMut<Sessions>,
     mut session_options: ResMut<SessionOptions>,
     menu_data: Res<MenuData>,
     meta: Root<GameMeta>,
+    cli_args: Res<CliArgs>,
+    storage: Res<Storage>,
+    network_simulator: Res<NetworkSimulator>,
 ) {
     match network_state.status {
         NetworkGameStatus::Searching => {
+            // Wait out the backoff after a failed attempt
+            if network_state
+                .retry_at
+                .is_some_and(|retry_at| Instant::now() < retry_at)
+            {
+                return;
+            }
+
+            // Use the command-line override, then the matchmaker picked in the settings
+            let settings = MatchmakerSettings::load(&storage);
+            let server_id = cli_args.matchmaking_server(settings.server(&meta.matchmaking_server));
+            let server = match parse_node_id(server_id) {
+                Ok(server) => server,
+                Err(err) => {
+                    network_state.fail(format!("Invalid matchmaker: {}", err));
+                    return;
+                }
+            };
+
             // Start searching for a match
             println!("Started searching for match!");
-            let server = meta.matchmaking_server.parse().expect("invalid server id");
             online::start_search_for_game(server, MAX_PLAYERS);
+            let now = Instant::now();
+            network_state.search_started.get_or_insert(now);
+            network_state.attempt_started = Some(now);
+            network_state.retry_at = None;
+            network_state.search_state = SearchState::Connecting;
             network_state.status = NetworkGameStatus::WaitingForPlayers;
         }
         NetworkGameStatus::WaitingForPlayers => {
-            // Check if a match has been found
-            let mut search_state = SearchState::Searching;
-            if let Some(online_socket) = online::update_search_for_game(&mut search_state) {
-                network_state.status = NetworkGameStatus::MatchFound;
-
-                // Create a new session runner for the game
-                let session_runner = Box::new(GgrsSessionRunner::<GameNetworkInputConfig>::new(
-                    FPS,
-                    GgrsSessionRunnerInfo::new(
-                        online_socket.ggrs_socket(),
-                        MAX_PREDICTION_WINDOW,
-                        Some(menu_data.input_delay_frames), // Use the custom input delay
-                    ),
-                ));
-
-                // Reset the network state and prepare to start the game
-                network_state.reset();
-                session_options.delete = true;
+            // Check if a match has been found, retry if the matchmaker reported an error
+            match poll_matchmaker(&mut network_state.search_state) {
+                Ok(Some(online_socket)) => {
+                    network_state.start_handshake(online_socket, &meta);
+                    return;
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    network_state.retry_search(error);
+                    return;
+                }
+            }
 
-                // Start the gameplay session
-                GameplayPlugin::start_gameplay_session(
-                    sessions,
-                    session_runner,
-                    online_socket.player_idx(),
-                );
+            // Retry if the matchmaker never answered
+            let timed_out = network_state
+                .attempt_started
+                .is_some_and(|attempt_started| {
+                    attempt_started.elapsed() >= MATCHMAKER_CONNECT_TIMEOUT
+                });
+            if matches!(network_state.search_state, SearchState::Connecting) && timed_out {
+                network_state.retry_search("The matchmaker didn't respond".to_string());
             }
         }
         NetworkGameStatus::MatchFound => {
-            // Logic primarily happens in waiting for players
+            let Some(online_socket) = network_state.socket.clone() else {
+                network_state.reset();
+                return;
+            };
+
+            let handshake = &mut network_state.handshake;
+            let started = *handshake.started.get_or_insert_with(Instant::now);
+            if started.elapsed() >= HANDSHAKE_TIMEOUT {
+                network_state.fail("The other player didn't respond");
+                return;
+            }
+
+            // Exchange the config and measure the round trip time
+            for (_, bytes) in online_socket.recv_reliable() {
+                if let Some(message) = ReliableMessage::from_bytes(&bytes) {
+                    handshake.receive(message, &online_socket);
+                }
+            }
+            handshake.send_ping(&online_socket);
+
+            // Wait for the other player's physics config, rules, name and input delay
+            // recommendation
+            let (
+                Some((remote_physics, remote_rules)),
+                Some(remote_name),
+                Some(rtt),
+                Some(remote_input_delay),
+            ) = (
+                handshake.remote_config.clone(),
+                handshake.remote_name.clone(),
+                handshake.rtt(),
+                handshake.remote_input_delay,
+            )
+            else {
+                return;
+            };
+
+            // Both players use the config of player 0 so the simulations stay identical
+            let local_physics = meta.gameplay.physics.clone();
+            let local_rules = meta.gameplay.rules.clone();
+            if remote_physics != local_physics || remote_rules != local_rules {
+                println!("Match config differs from the other player, using player 0's");
+            }
+            let (physics, rules) = if online_socket.player_idx() == 0 {
+                (local_physics, local_rules)
+            } else {
+                (remote_physics, remote_rules)
+            };
+
+            // In auto mode both players use the larger recommendation, otherwise the
+            // manually picked delay
+            let recommended_delay = recommended_input_delay(rtt);
+            let input_delay = if menu_data.input_delay_frames == AUTO_INPUT_DELAY {
+                recommended_delay.max(remote_input_delay)
+            } else {
+                menu_data.input_delay_frames
+            };
+            println!(
+                "RTT: {:?}, recommended input delay: {} (other player: {}), using {}",
+                rtt, recommended_delay, remote_input_delay, input_delay
+            );
+
+            // Create a new session runner for the game, watching its traffic to notice when the
+            // other player disconnects
+            let connection = GgrsConnection::default();
+            let session_runner = Box::new(GgrsSessionRunner::<GameNetworkInputConfig>::new(
+                FPS,
+                GgrsSessionRunnerInfo::new(
+                    connection.watch(network_simulator.wrap(online_socket.ggrs_socket())),
+                    MAX_PREDICTION_WINDOW,
+                    Some(input_delay),
+                ),
+            ));
+
+            // Reset the network state and prepare to start the game
+            network_state.reset();
+            session_options.delete = true;
+
+            // Start the gameplay session
+            GameplayPlugin::start_gameplay_session(
+                &mut sessions,
+                session_runner,
+                Some(online_socket.player_idx()),
+                default(),
+                physics,
+                rules,
+                GameplaySprites::from_meta(&meta.gameplay),
+                Some(online_socket.clone()),
+                Some(input_delay),
+            );
+            if let Some(session) = sessions.get_mut(SessionNames::GAMEPLAY) {
+                let local_player_idx = online_socket.player_idx() as usize;
+                let mut names = [remote_name.clone(), remote_name];
+                names[local_player_idx] = lan_host_name();
+                session.world.insert_resource(PlayerNames { names });
+            }
+            if let Some(session) = sessions.get_mut(SessionNames::GAMEPLAY_UI) {
+                session
+                    .world
+                    .insert_resource(ConnectionMonitor::new(connection));
+            }
         }
         NetworkGameStatus::Idle => {
             // Reset the network state
             network_state.reset();
         }
+        // Handled by handle_lan_menu_matchmaking
+        NetworkGameStatus::Hosting
+        | NetworkGameStatus::Joining
+        | NetworkGameStatus::Spectating
+        | NetworkGameStatus::Error(_) => {}
     }
 }
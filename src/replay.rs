@@ -0,0 +1,602 @@
+use crate::gameplay::{
+    return_to_main_menu, BotController, BotDifficultyMeta, BotPlayers, GameplayPlugin,
+    GameplaySprites, MatchRules, MatchState, PhysicsMeta,
+};
+use crate::input::{DensePlayerControl, MatchInputs};
+use crate::networking::FPS;
+use crate::{GameMeta, SessionNames};
+use bones_framework::input::PlayerControls;
+use bones_framework::networking::input::NetworkPlayerControl;
+use bones_framework::prelude::*;
+use egui::{Color32, RichText};
+use std::path::{Path, PathBuf};
+
+/// The folder replays are saved to
+pub const REPLAY_DIR: &str = "replays";
+/// The extension of replay files
+pub const REPLAY_EXTENSION: &str = "replay";
+/// The bytes every replay file starts with
+const REPLAY_MAGIC: &[u8; 4] = b"BVRP";
+/// The version of the replay format, bumped whenever the layout changes
+pub const REPLAY_FORMAT_VERSION: u16 = 1;
+/// The version of the game, replays only play back on the build they were recorded with
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The playback speeds which can be picked, in order
+const PLAYBACK_SPEEDS: [f64; 3] = [0.5, 1.0, 2.0];
+/// The maximum number of frames simulated in a single step, prevents spiraling after a long hitch
+const MAX_FRAMES_PER_STEP: u32 = 10;
+
+/// Everything needed to simulate a match again: its config and the inputs of every frame.
+///
+/// Replays are stored in a little-endian binary format:
+/// - header: magic, format version (u16), game version (string), physics config hash (u64),
+///   match rules (their length as a varint followed by their bytes), input delay (varint) and
+///   player info: the local player index (u8) followed by a flag per player telling if it's a bot,
+///   with the bot's difficulty, and the forfeit winner (u8, 0 if the match wasn't forfeited,
+///   otherwise the player index plus one)
+/// - inputs: the number of frames (varint), then runs of identical frames as a run length
+///   (varint) and the `DensePlayerControl` bits of both players (u32)
+/// - the CRC-32 of everything before it
+///
+/// Strings are stored as their length (varint) followed by their UTF-8 bytes.
+#[derive(Clone, Debug)]
+pub struct Replay {
+    /// The version of the game the replay was recorded with
+    pub game_version: String,
+    /// The hash of the physics config the match was played with
+    pub physics_hash: u64,
+    pub rules: MatchRules,
+    /// The input delay frames of the recording player, 0 for local matches
+    pub input_delay: u32,
+    /// The player the replay was recorded by
+    pub local_player_idx: u32,
+    /// The difficulty of each bot player, their inputs aren't recorded
+    pub bots: [Option<BotDifficultyMeta>; 2],
+    /// The player who won because the other one disconnected, the recording stops there
+    pub forfeit_winner: Option<u32>,
+    /// The inputs of both players for every frame, starting with the first one
+    pub inputs: Vec<[DensePlayerControl; 2]>,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self::new(0, &default(), default(), 0, &default())
+    }
+}
+
+impl Replay {
+    /// Creates an empty replay of a match with the given config, recorded by this build
+    pub fn new(
+        local_player_idx: u32,
+        physics: &PhysicsMeta,
+        rules: MatchRules,
+        input_delay: u32,
+        bot_players: &BotPlayers,
+    ) -> Self {
+        Self {
+            game_version: GAME_VERSION.to_string(),
+            physics_hash: physics.config_hash(),
+            rules,
+            input_delay,
+            local_player_idx,
+            bots: bot_players
+                .bots
+                .clone()
+                .map(|bot| bot.map(|bot| bot.difficulty)),
+            forfeit_winner: None,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Returns the bots of the replayed match
+    pub fn bot_players(&self) -> BotPlayers {
+        let mut bot_players = BotPlayers::default();
+        for (player_idx, difficulty) in self.bots.iter().enumerate() {
+            if let Some(difficulty) = difficulty {
+                bot_players.bots[player_idx] = Some(BotController::new(difficulty.clone()));
+            }
+        }
+        bot_players
+    }
+
+    /// Checks that the replay can be played by this build with the given physics, the simulation
+    /// would diverge from the recorded match otherwise.
+    pub fn check_compatible(&self, physics: &PhysicsMeta) -> Result<(), String> {
+        if self.game_version != GAME_VERSION {
+            return Err(format!(
+                "Recorded with game version {}, but this is version {}",
+                self.game_version, GAME_VERSION
+            ));
+        }
+        if self.physics_hash != physics.config_hash() {
+            return Err(format!(
+                "Recorded with different physics (config hash {:016x}, expected {:016x})",
+                self.physics_hash,
+                physics.config_hash()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns a new path in the replay folder, named after the current time
+    pub fn new_file_path() -> PathBuf {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        PathBuf::from(REPLAY_DIR).join(format!("replay_{}.{}", timestamp, REPLAY_EXTENSION))
+    }
+
+    /// Encodes the replay into its binary format
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        bytes.extend_from_slice(&REPLAY_FORMAT_VERSION.to_le_bytes());
+        write_string(&mut bytes, &self.game_version);
+        bytes.extend_from_slice(&self.physics_hash.to_le_bytes());
+        // Prefixed with their length, the rules of other builds are refused instead of misread
+        let rules = self.rules.to_bytes();
+        write_varint(&mut bytes, rules.len() as u64);
+        bytes.extend_from_slice(&rules);
+        write_varint(&mut bytes, self.input_delay as u64);
+
+        bytes.push(self.local_player_idx as u8);
+        for difficulty in &self.bots {
+            match difficulty {
+                Some(difficulty) => {
+                    bytes.push(1);
+                    write_string(&mut bytes, &difficulty.name);
+                    write_varint(&mut bytes, difficulty.reaction_delay_frames as u64);
+                    bytes.extend_from_slice(&difficulty.prediction_error.to_le_bytes());
+                    bytes.extend_from_slice(&difficulty.max_speed.to_le_bytes());
+                }
+                None => bytes.push(0),
+            }
+        }
+        bytes.push(self.forfeit_winner.map_or(0, |winner| winner as u8 + 1));
+
+        // Most frames repeat the inputs of the previous one, so they are run-length encoded
+        write_varint(&mut bytes, self.inputs.len() as u64);
+        let mut frames = self.inputs.iter().peekable();
+        while let Some(input) = frames.next() {
+            let mut run_length = 1;
+            while frames.next_if_eq(&input).is_some() {
+                run_length += 1;
+            }
+            write_varint(&mut bytes, run_length);
+            for player_input in input {
+                bytes.extend_from_slice(&player_input.bits().to_le_bytes());
+            }
+        }
+
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a replay created with `encode`, explains why if the bytes can't be decoded
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < REPLAY_MAGIC.len() || &bytes[..REPLAY_MAGIC.len()] != REPLAY_MAGIC {
+            return Err("Not a replay file".to_string());
+        }
+        let (content, crc) = bytes.split_at(bytes.len().saturating_sub(4).max(4));
+        let mut reader = ReplayReader {
+            bytes: content,
+            position: REPLAY_MAGIC.len(),
+        };
+
+        let format_version = u16::from_le_bytes(reader.read_array()?);
+        if format_version != REPLAY_FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported replay format version {}, this build reads version {}",
+                format_version, REPLAY_FORMAT_VERSION
+            ));
+        }
+        if crc.len() != 4 || crc32(content).to_le_bytes() != crc {
+            return Err("The replay file is corrupted (checksum mismatch)".to_string());
+        }
+
+        let game_version = reader.read_string()?;
+        let physics_hash = u64::from_le_bytes(reader.read_array()?);
+        let rules_len = reader.read_varint()? as usize;
+        let rules = MatchRules::from_bytes(reader.read_bytes(rules_len)?).ok_or_else(|| {
+            format!(
+                "Unsupported match rules, recorded with game version {}",
+                game_version
+            )
+        })?;
+        let input_delay = reader.read_varint()? as u32;
+
+        let local_player_idx = reader.read_u8()? as u32;
+        if local_player_idx > 1 {
+            return Err(format!("Invalid local player {}", local_player_idx));
+        }
+        let mut bots = [None, None];
+        for bot in &mut bots {
+            if reader.read_u8()? != 0 {
+                *bot = Some(BotDifficultyMeta {
+                    name: reader.read_string()?,
+                    reaction_delay_frames: reader.read_varint()? as u32,
+                    prediction_error: f32::from_le_bytes(reader.read_array()?),
+                    max_speed: f32::from_le_bytes(reader.read_array()?),
+                });
+            }
+        }
+        let forfeit_winner = match reader.read_u8()? {
+            0 => None,
+            winner @ 1..=2 => Some(winner as u32 - 1),
+            winner => return Err(format!("Invalid forfeit winner {}", winner - 1)),
+        };
+
+        let frame_count = reader.read_varint()? as usize;
+        let mut inputs = Vec::new();
+        while inputs.len() < frame_count {
+            let run_length = reader.read_varint()? as usize;
+            let input = [(); 2].map(|_| reader.read_array().map(u32::from_le_bytes));
+            let [Ok(player_0), Ok(player_1)] = input else {
+                return Err("The replay inputs are truncated".to_string());
+            };
+            if run_length == 0 || inputs.len() + run_length > frame_count {
+                return Err("The replay inputs are malformed".to_string());
+            }
+            let frame = [player_0, player_1].map(DensePlayerControl::from_bits);
+            inputs.resize(inputs.len() + run_length, frame);
+        }
+        if reader.position != content.len() {
+            return Err("Unexpected data after the replay inputs".to_string());
+        }
+
+        Ok(Self {
+            game_version,
+            physics_hash,
+            rules,
+            input_delay,
+            local_player_idx,
+            bots,
+            forfeit_winner,
+            inputs,
+        })
+    }
+
+    /// Writes the replay to the given file, creating its folder if needed
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, self.encode())
+    }
+
+    /// Reads a replay from the given file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+        Self::decode(&bytes)
+    }
+}
+
+/// Appends an unsigned LEB128 varint
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Appends a string as its length followed by its UTF-8 bytes
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    write_varint(bytes, value.len() as u64);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+/// Reads the values written to a replay in order
+struct ReplayReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ReplayReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.position.saturating_add(len);
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or("The replay file is truncated")?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_varint(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid varint in the replay file".to_string())
+    }
+
+    fn read_string(&mut self) -> Result<String, String> {
+        let len = self.read_varint()? as usize;
+        let bytes = self.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "Invalid text in the replay file".to_string())
+    }
+}
+
+/// CRC-32 (IEEE) of the given bytes
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Returns the replay files in the replay folder, newest first
+pub fn list_replays() -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(REPLAY_DIR) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == REPLAY_EXTENSION))
+        .collect();
+    // The file names contain the time they were recorded at
+    files.sort();
+    files.reverse();
+    files
+}
+
+/// The replays listed in the menu, refreshed whenever the replay browser is opened
+#[derive(HasSchema, Clone, Default)]
+pub struct ReplayBrowser {
+    pub files: Vec<PathBuf>,
+    /// Why the selected replay couldn't be played, empty if it could
+    pub error: String,
+}
+
+impl ReplayBrowser {
+    /// Lists the replay files again and clears the error
+    pub fn refresh(&mut self) {
+        self.files = list_replays();
+        self.error.clear();
+    }
+}
+
+/// The playback state of a replay, inserted into the world after every step for the UI
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct ReplayStatus {
+    /// The number of frames played so far
+    pub frame: usize,
+    pub total_frames: usize,
+    pub paused: bool,
+    pub speed: f64,
+    /// The player who won by forfeit once the end of the replay is reached
+    pub forfeit_winner: Option<u32>,
+}
+
+/// Session runner which plays the recorded inputs of a replay back.
+///
+/// Space pauses, the right arrow steps a single frame while paused and the up/down arrows change
+/// the playback speed.
+pub struct ReplaySessionRunner {
+    replay: Replay,
+    /// The index of the next frame to play
+    frame: usize,
+    paused: bool,
+    /// The index of the playback speed in PLAYBACK_SPEEDS
+    speed_idx: usize,
+    /// Whether a single frame should be played while paused
+    step_requested: bool,
+    accumulator: f64,
+    last_run: Option<Instant>,
+}
+
+impl ReplaySessionRunner {
+    /// Creates a new ReplaySessionRunner playing the given replay
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            frame: 0,
+            paused: false,
+            speed_idx: 1,
+            step_requested: false,
+            accumulator: 0.0,
+            last_run: None,
+        }
+    }
+
+    /// Handles the playback controls
+    fn handle_controls(&mut self, keyboard: &KeyboardInputs) {
+        for event in &keyboard.key_events {
+            if !event.button_state.pressed() {
+                continue;
+            }
+            match event.key_code {
+                Set(KeyCode::Space) => self.paused = !self.paused,
+                Set(KeyCode::Right) if self.paused => self.step_requested = true,
+                Set(KeyCode::Up) => {
+                    self.speed_idx = (self.speed_idx + 1).min(PLAYBACK_SPEEDS.len() - 1);
+                }
+                Set(KeyCode::Down) => self.speed_idx = self.speed_idx.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+
+    /// Simulates the next frame of the replay with its recorded inputs
+    fn play_frame(&mut self, world: &mut World, stages: &mut SystemStages) {
+        let inputs = self.replay.inputs[self.frame];
+        {
+            let mut match_inputs = world.resource_mut::<MatchInputs>();
+            for (player_idx, dense) in inputs.iter().enumerate() {
+                // The bots write their own inputs again
+                if self.replay.bots[player_idx].is_none() {
+                    match_inputs
+                        .get_control_mut(player_idx)
+                        .update_from_dense(dense);
+                }
+            }
+        }
+        world
+            .resource_mut::<Time>()
+            .advance_exact(std::time::Duration::from_secs_f64(1.0 / FPS as f64));
+        stages.run(world);
+        self.frame += 1;
+    }
+}
+
+impl SessionRunner for ReplaySessionRunner {
+    /// Plays the replay at a fixed FPS scaled by the playback speed
+    fn step(&mut self, now: Instant, world: &mut World, stages: &mut SystemStages) {
+        if let Some(keyboard) = world.get_resource::<KeyboardInputs>() {
+            self.handle_controls(&keyboard);
+        }
+
+        let frame_time = 1.0 / FPS as f64;
+        let last_run = self.last_run.unwrap_or(now);
+        if !self.paused {
+            self.accumulator +=
+                now.duration_since(last_run).as_secs_f64() * PLAYBACK_SPEEDS[self.speed_idx];
+        }
+        self.last_run = Some(now);
+
+        let mut frames_run = 0;
+        while self.accumulator >= frame_time && frames_run < MAX_FRAMES_PER_STEP {
+            self.accumulator -= frame_time;
+            frames_run += 1;
+            if self.frame >= self.replay.inputs.len() {
+                break;
+            }
+            self.play_frame(world, stages);
+        }
+        if frames_run == MAX_FRAMES_PER_STEP {
+            self.accumulator = 0.0;
+        }
+        if std::mem::take(&mut self.step_requested) && self.frame < self.replay.inputs.len() {
+            self.play_frame(world, stages);
+        }
+
+        // Pause at the end, so the last frame stays on screen
+        let finished = self.frame >= self.replay.inputs.len();
+        if finished {
+            self.paused = true;
+            // The inputs stop where the other player disconnected, the forfeit isn't simulated
+            if let Some(winner) = self.replay.forfeit_winner {
+                let mut match_state = world.resource_mut::<MatchState>();
+                if match_state.forfeit_winner().is_none() {
+                    match_state.forfeit(winner as usize);
+                }
+            }
+        }
+        world.insert_resource(ReplayStatus {
+            frame: self.frame,
+            total_frames: self.replay.inputs.len(),
+            paused: self.paused,
+            speed: PLAYBACK_SPEEDS[self.speed_idx],
+            forfeit_winner: self.replay.forfeit_winner.filter(|_| finished),
+        });
+    }
+
+    /// Plays the replay again from the start
+    fn restart_session(&mut self) {
+        self.frame = 0;
+        self.accumulator = 0.0;
+        self.last_run = None;
+    }
+
+    /// Inputs come from the replay, so local input is never read
+    fn disable_local_input(&mut self, _input_disabled: bool) {}
+}
+
+/// Starts a gameplay session playing the given replay, unless it can't be played by this build
+pub fn start_replay_session(
+    sessions: &mut Sessions,
+    meta: &GameMeta,
+    replay: Replay,
+) -> Result<(), String> {
+    replay.check_compatible(&meta.gameplay.physics)?;
+    let local_player_idx = replay.local_player_idx;
+    let bot_players = replay.bot_players();
+    let rules = replay.rules.clone();
+    GameplayPlugin::start_gameplay_session(
+        sessions,
+        Box::new(ReplaySessionRunner::new(replay)),
+        Some(local_player_idx),
+        bot_players,
+        meta.gameplay.physics.clone(),
+        rules,
+        GameplaySprites::from_meta(&meta.gameplay),
+        None,
+        None,
+    );
+    Ok(())
+}
+
+/// Draws the playback state and controls of a replay, Escape returns to the menu
+pub fn draw_replay_controls(
+    mut sessions: ResMut<Sessions>,
+    mut session_options: ResMut<SessionOptions>,
+    keyboard: Res<KeyboardInputs>,
+    ctx: Res<EguiCtx>,
+) {
+    let Some(status) = sessions
+        .get(SessionNames::GAMEPLAY)
+        .and_then(|session| session.world.get_resource::<ReplayStatus>())
+        .map(|status| status.clone())
+    else {
+        return;
+    };
+
+    let escape_pressed = keyboard
+        .key_events
+        .iter()
+        .any(|event| event.key_code == Set(KeyCode::Escape) && event.button_state.pressed());
+    if escape_pressed {
+        return_to_main_menu(&mut sessions, &mut session_options);
+        return;
+    }
+
+    egui::Area::new("replay_controls")
+        .anchor(egui::Align2::CENTER_BOTTOM, (0.0, -20.0))
+        .show(&ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                let state = if let Some(winner) = status.forfeit_winner {
+                    format!("Player {} won by forfeit", winner + 1)
+                } else if status.frame >= status.total_frames {
+                    "End of replay".to_string()
+                } else if status.paused {
+                    "Paused".to_string()
+                } else {
+                    format!("{}x", status.speed)
+                };
+                let text = RichText::new(format!(
+                    "REPLAY  {} / {}  {}",
+                    status.frame, status.total_frames, state
+                ))
+                .color(Color32::WHITE)
+                .size(24.0)
+                .strong();
+                ui.label(text);
+                let text =
+                    RichText::new("Space: Pause    Right: Step    Up/Down: Speed    Escape: Leave")
+                        .color(Color32::WHITE)
+                        .size(16.0);
+                ui.label(text);
+            });
+        });
+}
@@ -0,0 +1,44 @@
+use crate::networking::parse_node_id;
+use bones_framework::prelude::*;
+
+/// The matchmaking servers saved by the player, persisted with the bones Storage
+#[derive(HasSchema, Clone, Debug, Default)]
+#[repr(C)]
+pub struct MatchmakerSettings {
+    /// The node IDs of the saved matchmakers
+    pub servers: SVec<String>,
+    /// The node ID of the matchmaker in use, empty for the one in `game.yaml`
+    pub selected: String,
+}
+
+impl MatchmakerSettings {
+    /// Returns the saved settings, or the defaults if none were saved yet
+    pub fn load(storage: &Storage) -> Self {
+        storage.get::<Self>().cloned().unwrap_or_default()
+    }
+
+    /// Stores the settings and writes them to disk
+    pub fn save(&self, storage: &mut Storage) {
+        storage.insert(self.clone());
+        storage.save();
+    }
+
+    /// Returns the node ID of the matchmaker to use
+    pub fn server<'a>(&'a self, default_server: &'a str) -> &'a str {
+        if self.selected.is_empty() {
+            default_server
+        } else {
+            &self.selected
+        }
+    }
+
+    /// Validates the node ID, then saves and selects it
+    pub fn add(&mut self, node_id: &str) -> Result<(), String> {
+        let node_id: String = parse_node_id(node_id)?;
+        if !self.servers.iter().any(|server| *server == node_id) {
+            self.servers.push(node_id.clone());
+        }
+        self.selected = node_id;
+        Ok(())
+    }
+}
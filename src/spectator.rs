@@ -0,0 +1,521 @@
+use crate::gameplay::{
+    return_to_main_menu, GameplayPlugin, GameplaySprites, MatchRules, MatchState, PhysicsMeta,
+    PlayerNames, ReplayRecorder, SimulationFrame,
+};
+use crate::input::{DensePlayerControl, MatchInputs};
+use crate::networking::{
+    lan_host_name, MatchSocket, NetworkGameState, NetworkGameStatus, ReliableMessage, FPS,
+    NODE_ADDR_PROPERTY, SPECTATE_SERVICE_TYPE,
+};
+use crate::{GameMeta, SessionNames};
+use bones_framework::input::PlayerControls;
+use bones_framework::networking::input::NetworkPlayerControl;
+use bones_framework::networking::{lan, NetworkMatchSocket, SocketTarget};
+use bones_framework::prelude::*;
+use egui::{Color32, RichText};
+use std::time::Duration;
+
+/// How many frames the spectator plays behind the newest confirmed frame it received, so that
+/// the match keeps playing smoothly while the next inputs are on their way
+pub const SPECTATOR_DELAY_FRAMES: usize = 60;
+/// How far behind the spectator may fall before it fast-forwards to catch up
+const CATCH_UP_FRAMES: usize = 180;
+/// The maximum number of frames simulated in a single step
+const MAX_FRAMES_PER_STEP: usize = 10;
+/// The minimum number of newly confirmed frames sent to the spectator at once
+const SEND_INTERVAL_FRAMES: usize = 6;
+/// The maximum number of frames sent in a single message, so joining late doesn't produce one
+/// huge message
+const MAX_FRAMES_PER_MESSAGE: usize = 600;
+/// How long joining waits for the host to send the match
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the host may stay silent before the connection is considered lost
+const HOST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The confirmed inputs received from the host, without gaps. The inputs at index `i` are the
+/// ones simulated at frame `i + 1`.
+#[derive(Clone, Debug, Default)]
+pub struct ConfirmedInputBuffer {
+    pub inputs: Vec<[DensePlayerControl; 2]>,
+}
+
+impl ConfirmedInputBuffer {
+    /// Appends the inputs starting at index `first_frame`, skipping the ones already received.
+    /// Returns false if they don't follow the received inputs.
+    pub fn receive(&mut self, first_frame: u32, inputs: &[[DensePlayerControl; 2]]) -> bool {
+        let Some(already_received) = self.inputs.len().checked_sub(first_frame as usize) else {
+            return false;
+        };
+        self.inputs
+            .extend(inputs.iter().skip(already_received).copied());
+        true
+    }
+
+    /// Returns the number of frames received
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Returns true if no frame was received yet
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+/// Streams the match to a spectator, lives in the gameplay_ui session. A player only hosts a
+/// spectator after asking to with F3, the spectator joins with the ticket shown then.
+#[derive(HasSchema, Clone, Default)]
+pub struct SpectatorHost {
+    /// Whether the local player asked to let a spectator watch
+    requested: bool,
+    /// The server advertised while waiting for a spectator
+    host_info: Option<lan::ServerInfo>,
+    /// Whether the server has been started
+    hosting: bool,
+    /// The number of players of the server, including the host
+    joined_players: usize,
+    /// The socket of the joined spectator
+    socket: Option<NetworkMatchSocket>,
+    /// The number of confirmed frames sent to the spectator
+    frames_sent: usize,
+}
+
+impl SpectatorHost {
+    /// Returns the ticket spectators join the match with, while waiting for one
+    pub fn ticket(&self) -> Option<&str> {
+        self.host_info
+            .as_ref()
+            .filter(|_| self.hosting)?
+            .service
+            .get_property_val_str(NODE_ADDR_PROPERTY)
+    }
+
+    /// Stops waiting for a spectator and tells the joined one that the match is over
+    fn stop(&mut self) {
+        if let Some(server) = self.host_info.as_ref().filter(|_| self.hosting) {
+            lan::stop_server(server);
+        }
+        if let Some(socket) = self.socket.take() {
+            socket.send_reliable(SocketTarget::All, &ReliableMessage::LeftMatch.to_bytes());
+        }
+        self.hosting = false;
+        self.joined_players = 0;
+        self.frames_sent = 0;
+    }
+}
+
+/// Toggles hosting a spectator with F3 during an online match. Once asked to, waits for a
+/// spectator, then sends it the match config, the names of the players and every confirmed frame
+/// of the match.
+pub fn host_spectators(
+    sessions: Res<Sessions>,
+    match_socket: Res<MatchSocket>,
+    keyboard: Res<KeyboardInputs>,
+    mut spectator_host: ResMut<SpectatorHost>,
+) {
+    if match_socket.socket.is_none() {
+        return;
+    }
+    let Some(session) = sessions.get(SessionNames::GAMEPLAY) else {
+        return;
+    };
+    // The match is torn down once the gameplay session stops
+    if !session.active {
+        spectator_host.stop();
+        return;
+    }
+
+    let f3_pressed = keyboard
+        .key_events
+        .iter()
+        .any(|event| event.key_code == Set(KeyCode::F3) && event.button_state.pressed());
+    if f3_pressed {
+        spectator_host.requested = !spectator_host.requested;
+        if !spectator_host.requested {
+            spectator_host.stop();
+        }
+    }
+    if !spectator_host.requested {
+        return;
+    }
+
+    let (Some(physics), Some(match_state), Some(player_names)) = (
+        session.world.get_resource::<PhysicsMeta>(),
+        session.world.get_resource::<MatchState>(),
+        session.world.get_resource::<PlayerNames>(),
+    ) else {
+        return;
+    };
+
+    let SpectatorHost {
+        requested: _,
+        host_info,
+        hosting,
+        joined_players,
+        socket,
+        frames_sent,
+    } = &mut *spectator_host;
+    let Some(socket) = socket else {
+        let (_, server) = lan::prepare_to_host(host_info, SPECTATE_SERVICE_TYPE, &lan_host_name());
+        if !*hosting {
+            lan::start_server(server.clone(), 2);
+            *hosting = true;
+        }
+        if let Some(spectator_socket) = lan::wait_players(joined_players, server) {
+            println!("Spectator joined the match");
+            *hosting = false;
+            for message in [
+                ReliableMessage::MatchConfig(physics.clone(), match_state.rules().clone()),
+                ReliableMessage::PlayerNames(player_names.names.clone()),
+            ] {
+                spectator_socket.send_reliable(SocketTarget::All, &message.to_bytes());
+            }
+            *socket = Some(spectator_socket);
+        }
+        return;
+    };
+
+    // Only confirmed inputs are sent, the spectator never has to roll back
+    let (Some(replay_recorder), Some(simulation_frame)) = (
+        session.world.get_resource::<ReplayRecorder>(),
+        session.world.get_resource::<SimulationFrame>(),
+    ) else {
+        return;
+    };
+    let inputs = replay_recorder.confirmed_inputs(*frames_sent, simulation_frame.confirmed_frame());
+    if inputs.len() < SEND_INTERVAL_FRAMES {
+        return;
+    }
+    for chunk in inputs.chunks(MAX_FRAMES_PER_MESSAGE) {
+        let message = ReliableMessage::ConfirmedInputs(*frames_sent as u32, chunk.to_vec());
+        socket.send_reliable(SocketTarget::All, &message.to_bytes());
+        *frames_sent += chunk.len();
+    }
+}
+
+/// Draws how to let a spectator watch an online match in a corner of the match, along with the
+/// ticket to share while waiting for one
+pub fn draw_spectator_host(
+    match_socket: Res<MatchSocket>,
+    spectator_host: Res<SpectatorHost>,
+    ctx: Res<EguiCtx>,
+) {
+    if match_socket.socket.is_none() {
+        return;
+    }
+
+    egui::Area::new("spectator_host")
+        .anchor(egui::Align2::LEFT_BOTTOM, (10.0, -10.0))
+        .show(&ctx, |ui| {
+            let text = if !spectator_host.requested {
+                "F3: Let a spectator watch"
+            } else if spectator_host.socket.is_some() {
+                "A spectator is watching    F3: Stop"
+            } else {
+                "Waiting for a spectator    F3: Stop"
+            };
+            ui.label(RichText::new(text).color(Color32::WHITE).size(16.0));
+            if let Some(mut ticket) = spectator_host.ticket() {
+                ui.label(
+                    RichText::new("Spectator ticket:")
+                        .color(Color32::WHITE)
+                        .size(16.0),
+                );
+                // A read-only text field, so the ticket can be selected and copied
+                ui.add(
+                    egui::TextEdit::singleline(&mut ticket)
+                        .desired_width(400.0)
+                        .font(egui::TextStyle::Monospace),
+                );
+            }
+        });
+}
+
+/// The match received while joining as a spectator, lives in the menu session
+#[derive(HasSchema, Clone, Default)]
+pub struct SpectatorJoin {
+    config: Option<(PhysicsMeta, MatchRules)>,
+    names: Option<[String; 2]>,
+    inputs: ConfirmedInputBuffer,
+    /// When the connection to the host was established
+    started: Option<Instant>,
+}
+
+/// Collects the match config, the names of the players and the inputs sent by the host once
+/// connected as a spectator, then starts the spectator session.
+pub fn handle_spectator_join(
+    mut network_state: ResMut<NetworkGameState>,
+    mut spectator_join: ResMut<SpectatorJoin>,
+    mut sessions: ResMut<Sessions>,
+    mut session_options: ResMut<SessionOptions>,
+    meta: Root<GameMeta>,
+) {
+    if network_state.status != NetworkGameStatus::Spectating {
+        *spectator_join = default();
+        return;
+    }
+    let Some(socket) = network_state.socket.clone() else {
+        return;
+    };
+    let started = *spectator_join.started.get_or_insert_with(Instant::now);
+
+    for (_, bytes) in socket.recv_reliable() {
+        match ReliableMessage::from_bytes(&bytes) {
+            Some(ReliableMessage::MatchConfig(physics, rules)) => {
+                spectator_join.config = Some((physics, rules));
+            }
+            Some(ReliableMessage::PlayerNames(names)) => spectator_join.names = Some(names),
+            Some(ReliableMessage::ConfirmedInputs(first_frame, inputs)) => {
+                if !spectator_join.inputs.receive(first_frame, &inputs) {
+                    network_state.fail("Part of the match was lost on the way from the host");
+                    return;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (Some((physics, rules)), Some(names)) =
+        (spectator_join.config.clone(), spectator_join.names.clone())
+    else {
+        if started.elapsed() >= JOIN_TIMEOUT {
+            network_state.fail("The host didn't send the match to spectate");
+        }
+        return;
+    };
+
+    println!("Spectating {} vs {}", names[0], names[1]);
+    let inputs = std::mem::take(&mut spectator_join.inputs);
+    *spectator_join = default();
+    network_state.reset();
+    session_options.delete = true;
+    start_spectator_session(&mut sessions, &meta, socket, physics, rules, names, inputs);
+}
+
+/// The playback state of a spectated match, inserted into the world after every step
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct SpectatorStatus {
+    /// The number of received frames not played yet
+    pub frames_behind: usize,
+    /// Whether the match is fast-forwarded to catch up with the host
+    pub catching_up: bool,
+    /// Whether playback is stalled until the host sends more inputs
+    pub waiting: bool,
+    /// Whether the host left the match or the connection to it was lost
+    pub host_left: bool,
+    /// Whether inputs were missing from the stream, nothing after them can be played
+    pub inputs_lost: bool,
+}
+
+/// Session runner which plays the confirmed inputs streamed by the host of an online match,
+/// a fixed number of frames behind the newest one. It never sends any input.
+pub struct SpectatorSessionRunner {
+    socket: NetworkMatchSocket,
+    buffer: ConfirmedInputBuffer,
+    /// The index of the next frame to play
+    frame: usize,
+    host_left: bool,
+    inputs_lost: bool,
+    last_message_received: Option<Instant>,
+    accumulator: f64,
+    last_run: Option<Instant>,
+}
+
+impl SpectatorSessionRunner {
+    /// Creates a new SpectatorSessionRunner receiving inputs from the given socket, starting
+    /// with the inputs received while joining
+    pub fn new(socket: NetworkMatchSocket, buffer: ConfirmedInputBuffer) -> Self {
+        Self {
+            socket,
+            buffer,
+            frame: 0,
+            host_left: false,
+            inputs_lost: false,
+            last_message_received: None,
+            accumulator: 0.0,
+            last_run: None,
+        }
+    }
+
+    /// Receives the inputs sent by the host, and notices when it is gone. Inputs which don't
+    /// follow the received ones mean some were lost, so the stream is given up on.
+    fn receive_inputs(&mut self, now: Instant) {
+        let last_message_received = *self.last_message_received.get_or_insert(now);
+        let mut received = false;
+        for (_, bytes) in self.socket.recv_reliable() {
+            received = true;
+            match ReliableMessage::from_bytes(&bytes) {
+                Some(ReliableMessage::ConfirmedInputs(first_frame, inputs)) => {
+                    if !self.buffer.receive(first_frame, &inputs) {
+                        println!("Spectated inputs are missing before frame {}", first_frame);
+                        self.inputs_lost = true;
+                        self.host_left = true;
+                    }
+                }
+                Some(ReliableMessage::LeftMatch) => self.host_left = true,
+                _ => {}
+            }
+        }
+        if received {
+            self.last_message_received = Some(now);
+        } else if now.duration_since(last_message_received) >= HOST_TIMEOUT {
+            self.host_left = true;
+        }
+    }
+
+    /// Simulates the next frame with the received inputs
+    fn play_frame(&mut self, world: &mut World, stages: &mut SystemStages) {
+        let inputs = self.buffer.inputs[self.frame];
+        {
+            let mut match_inputs = world.resource_mut::<MatchInputs>();
+            for (player_idx, dense) in inputs.iter().enumerate() {
+                match_inputs
+                    .get_control_mut(player_idx)
+                    .update_from_dense(dense);
+            }
+        }
+        world
+            .resource_mut::<Time>()
+            .advance_exact(std::time::Duration::from_secs_f64(1.0 / FPS as f64));
+        stages.run(world);
+        self.frame += 1;
+    }
+}
+
+impl SessionRunner for SpectatorSessionRunner {
+    /// Plays the received frames at a fixed FPS, fast-forwarding when far behind the host
+    fn step(&mut self, now: Instant, world: &mut World, stages: &mut SystemStages) {
+        if !self.host_left {
+            self.receive_inputs(now);
+        }
+
+        // Once the host is gone there is nothing to wait for, so the match is played to the end
+        let target = if self.host_left {
+            self.buffer.len()
+        } else {
+            self.buffer.len().saturating_sub(SPECTATOR_DELAY_FRAMES)
+        };
+        let catching_up = target.saturating_sub(self.frame) > CATCH_UP_FRAMES;
+
+        let frame_time = 1.0 / FPS as f64;
+        let last_run = self.last_run.unwrap_or(now);
+        self.accumulator += now.duration_since(last_run).as_secs_f64();
+        self.last_run = Some(now);
+
+        let mut frames_run = 0;
+        while self.frame < target
+            && frames_run < MAX_FRAMES_PER_STEP
+            && (catching_up || self.accumulator >= frame_time)
+        {
+            self.accumulator = (self.accumulator - frame_time).max(0.0);
+            frames_run += 1;
+            self.play_frame(world, stages);
+        }
+        // Don't build up time while waiting for inputs, it would be played back too fast
+        let waiting = self.frame >= target;
+        if waiting || frames_run == MAX_FRAMES_PER_STEP {
+            self.accumulator = 0.0;
+        }
+
+        world.insert_resource(SpectatorStatus {
+            frames_behind: self.buffer.len() - self.frame,
+            catching_up,
+            waiting: waiting && !self.host_left,
+            host_left: self.host_left,
+            inputs_lost: self.inputs_lost,
+        });
+    }
+
+    /// Plays the match again from the start, fast-forwarding to the host
+    fn restart_session(&mut self) {
+        self.frame = 0;
+        self.accumulator = 0.0;
+        self.last_run = None;
+    }
+
+    /// Spectators never send input, so local input is never read
+    fn disable_local_input(&mut self, _input_disabled: bool) {}
+}
+
+/// Starts a gameplay session spectating the match streamed through the given socket. There is no
+/// local player, so both players are only named.
+pub fn start_spectator_session(
+    sessions: &mut Sessions,
+    meta: &GameMeta,
+    socket: NetworkMatchSocket,
+    physics: PhysicsMeta,
+    rules: MatchRules,
+    names: [String; 2],
+    inputs: ConfirmedInputBuffer,
+) {
+    GameplayPlugin::start_gameplay_session(
+        sessions,
+        Box::new(SpectatorSessionRunner::new(socket, inputs)),
+        None,
+        default(),
+        physics,
+        rules,
+        GameplaySprites::from_meta(&meta.gameplay),
+        None,
+        None,
+    );
+    if let Some(session) = sessions.get_mut(SessionNames::GAMEPLAY) {
+        session.world.insert_resource(PlayerNames { names });
+    }
+}
+
+/// Draws the spectator banner, Escape returns to the menu
+pub fn draw_spectator_status(
+    mut sessions: ResMut<Sessions>,
+    mut session_options: ResMut<SessionOptions>,
+    keyboard: Res<KeyboardInputs>,
+    ctx: Res<EguiCtx>,
+) {
+    let Some(status) = sessions
+        .get(SessionNames::GAMEPLAY)
+        .and_then(|session| session.world.get_resource::<SpectatorStatus>())
+        .map(|status| status.clone())
+    else {
+        return;
+    };
+
+    let escape_pressed = keyboard
+        .key_events
+        .iter()
+        .any(|event| event.key_code == Set(KeyCode::Escape) && event.button_state.pressed());
+    if escape_pressed {
+        return_to_main_menu(&mut sessions, &mut session_options);
+        return;
+    }
+
+    egui::Area::new("spectator_status")
+        .anchor(egui::Align2::CENTER_BOTTOM, (0.0, -20.0))
+        .show(&ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                let state = if status.inputs_lost && status.frames_behind == 0 {
+                    "Part of the match was lost, the rest can't be shown".to_string()
+                } else if status.inputs_lost {
+                    "Part of the match was lost, playing what was received".to_string()
+                } else if status.host_left && status.frames_behind == 0 {
+                    "The match has ended".to_string()
+                } else if status.host_left {
+                    "The host left, playing the rest of the match".to_string()
+                } else if status.catching_up {
+                    format!("Catching up ({} frames behind)", status.frames_behind)
+                } else if status.waiting {
+                    "Waiting for the host...".to_string()
+                } else {
+                    String::new()
+                };
+                let text = RichText::new(format!("SPECTATING  {}", state))
+                    .color(Color32::WHITE)
+                    .size(24.0)
+                    .strong();
+                ui.label(text);
+                let text = RichText::new("Escape: Leave")
+                    .color(Color32::WHITE)
+                    .size(16.0);
+                ui.label(text);
+            });
+        });
+}
@@ -0,0 +1,294 @@
+use crate::gameplay::{
+    return_to_main_menu, BotPlayers, GameplayPlugin, GameplaySprites, SimulationFrame,
+    StateChecksum, StateChecksums,
+};
+use crate::input::{DensePlayerControl, MatchInputs};
+use crate::networking::FPS;
+use crate::{GameMeta, SessionNames};
+use bones_framework::input::PlayerControls;
+use bones_framework::networking::input::NetworkPlayerControl;
+use bones_framework::prelude::*;
+use egui::{Color32, RichText};
+use std::collections::VecDeque;
+
+/// The default number of frames rolled back and re-simulated every frame
+pub const DEFAULT_CHECK_DISTANCE: usize = 2;
+
+/// The inputs fed to the players during a sync test
+#[derive(Clone, Debug)]
+pub enum SyncTestInputs {
+    /// Pseudo random movement and jumps, generated deterministically from the seed
+    Random { seed: u32 },
+    /// The given inputs for both players, looped once they run out
+    Scripted(Vec<[DensePlayerControl; 2]>),
+}
+
+/// The result of a sync test so far, inserted into the world after every step
+#[derive(HasSchema, Clone, Debug, Default)]
+pub struct SyncTestReport {
+    /// The number of frames which were re-simulated and compared
+    pub frames_checked: u32,
+    /// The first frame whose re-simulation didn't match, with the original and re-simulated state
+    pub mismatch: Option<(StateChecksum, StateChecksum)>,
+}
+
+impl SyncTestReport {
+    /// Returns a single line describing the result so far
+    pub fn summary(&self) -> String {
+        match &self.mismatch {
+            Some((original, resimulated)) => format!(
+                "Sync test failed after {} frames: mismatch at frame {} (original {:#018x}, resimulated {:#018x})",
+                self.frames_checked, original.frame, original.checksum, resimulated.checksum
+            ),
+            None => format!(
+                "Sync test passed {} frames without a mismatch",
+                self.frames_checked
+            ),
+        }
+    }
+}
+
+/// Session runner which rolls back and re-simulates the last `check_distance` frames every frame,
+/// comparing the state checksums of both simulations, like the GGRS sync test session.
+///
+/// Any state which doesn't survive a snapshot/restore (e.g. `Instant`s or data outside the world)
+/// shows up as a mismatch.
+pub struct SyncTestSessionRunner {
+    inputs: SyncTestInputs,
+    check_distance: usize,
+    /// State of the pseudo random input generator
+    rng_state: u32,
+    /// The number of inputs generated so far
+    input_count: usize,
+    /// World snapshots taken before each of the last frames, along with the inputs of that frame
+    history: VecDeque<(World, [DensePlayerControl; 2])>,
+    /// The checksums produced by the first simulation of the frames in history
+    checksums: VecDeque<StateChecksum>,
+    report: SyncTestReport,
+    /// Whether frames run as fast as possible instead of at the target FPS
+    headless: bool,
+    accumulator: f64,
+    last_run: Option<Instant>,
+}
+
+impl SyncTestSessionRunner {
+    /// Creates a new SyncTestSessionRunner which runs in real-time at the target FPS
+    pub fn new(inputs: SyncTestInputs, check_distance: usize) -> Self {
+        let rng_state = match inputs {
+            SyncTestInputs::Random { seed } => seed.max(1),
+            SyncTestInputs::Scripted(_) => 1,
+        };
+        Self {
+            inputs,
+            check_distance: check_distance.max(1),
+            rng_state,
+            input_count: 0,
+            history: VecDeque::new(),
+            checksums: VecDeque::new(),
+            report: default(),
+            headless: false,
+            accumulator: 0.0,
+            last_run: None,
+        }
+    }
+
+    /// Creates a new SyncTestSessionRunner which simulates a single frame per step, for tests
+    pub fn headless(inputs: SyncTestInputs, check_distance: usize) -> Self {
+        Self {
+            headless: true,
+            ..Self::new(inputs, check_distance)
+        }
+    }
+
+    /// Returns the inputs of both players for the next frame
+    fn next_inputs(&mut self) -> [DensePlayerControl; 2] {
+        self.input_count += 1;
+        match &self.inputs {
+            SyncTestInputs::Random { .. } => [0, 1].map(|_| {
+                // xorshift32
+                self.rng_state ^= self.rng_state << 13;
+                self.rng_state ^= self.rng_state >> 17;
+                self.rng_state ^= self.rng_state << 5;
+                let direction = (self.rng_state % 3) as f32 - 1.0;
+                let jump = (self.rng_state >> 8) % 8 == 0;
+                DensePlayerControl::new(Vec2::new(direction, 0.0), jump, false, false)
+            }),
+            SyncTestInputs::Scripted(inputs) if !inputs.is_empty() => {
+                inputs[(self.input_count - 1) % inputs.len()]
+            }
+            SyncTestInputs::Scripted(_) => [default(); 2],
+        }
+    }
+
+    /// Simulates a single frame with the given inputs and returns the state checksum
+    fn advance_frame(
+        world: &mut World,
+        stages: &mut SystemStages,
+        inputs: &[DensePlayerControl; 2],
+    ) -> StateChecksum {
+        {
+            let mut match_inputs = world.resource_mut::<MatchInputs>();
+            for (player_idx, dense) in inputs.iter().enumerate() {
+                match_inputs
+                    .get_control_mut(player_idx)
+                    .update_from_dense(dense);
+            }
+        }
+        world
+            .resource_mut::<Time>()
+            .advance_exact(std::time::Duration::from_secs_f64(1.0 / FPS as f64));
+        stages.run(world);
+
+        let frame = world.resource::<SimulationFrame>().frame;
+        world
+            .resource::<StateChecksums>()
+            .get(frame)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Simulates the next frame, then rolls back and re-simulates the last frames
+    fn sync_test_frame(&mut self, world: &mut World, stages: &mut SystemStages) {
+        // Record a checksum every frame
+        world.resource_mut::<StateChecksums>().interval = 1;
+
+        let inputs = self.next_inputs();
+        self.history.push_back((world.clone(), inputs));
+        let checksum = Self::advance_frame(world, stages, &inputs);
+        self.checksums.push_back(checksum);
+        if self.history.len() > self.check_distance {
+            self.history.pop_front();
+            self.checksums.pop_front();
+        }
+        if self.history.len() < self.check_distance {
+            return;
+        }
+
+        // Roll back to the oldest snapshot and compare the re-simulated frames
+        *world = self.history[0].0.clone();
+        for ((_, inputs), original) in self.history.iter().zip(self.checksums.iter()) {
+            let resimulated = Self::advance_frame(world, stages, inputs);
+            if resimulated.checksum != original.checksum && self.report.mismatch.is_none() {
+                println!(
+                    "Sync test mismatch at frame {}!\n  Original    ({:#018x}): {}\n  Resimulated ({:#018x}): {}",
+                    original.frame,
+                    original.checksum,
+                    original.description,
+                    resimulated.checksum,
+                    resimulated.description
+                );
+                self.report.mismatch = Some((original.clone(), resimulated));
+            }
+        }
+        self.report.frames_checked += 1;
+    }
+}
+
+impl SessionRunner for SyncTestSessionRunner {
+    /// Runs the sync test at a fixed FPS, or a single frame per step when headless
+    fn step(&mut self, now: Instant, world: &mut World, stages: &mut SystemStages) {
+        if self.headless {
+            self.sync_test_frame(world, stages);
+        } else {
+            let frame_time = 1.0 / FPS as f64;
+            let last_run = self.last_run.unwrap_or(now);
+            self.accumulator += now.duration_since(last_run).as_secs_f64();
+            self.last_run = Some(now);
+
+            // Never catch up on more than one frame, the sync test is already expensive
+            if self.accumulator >= frame_time {
+                self.accumulator = 0.0;
+                self.sync_test_frame(world, stages);
+            }
+        }
+
+        world.insert_resource(self.report.clone());
+    }
+
+    /// Clears the history so the restarted session isn't compared against the old one
+    fn restart_session(&mut self) {
+        self.history.clear();
+        self.checksums.clear();
+        self.report = default();
+        self.accumulator = 0.0;
+        self.last_run = None;
+    }
+
+    /// Inputs are generated by the runner, so local input is never read
+    fn disable_local_input(&mut self, _input_disabled: bool) {}
+}
+
+/// Starts a gameplay session running under the sync test runner with random inputs
+pub fn start_sync_test_session(sessions: &mut Sessions, meta: &GameMeta, seed: u32) {
+    let session_runner = Box::new(SyncTestSessionRunner::new(
+        SyncTestInputs::Random { seed },
+        DEFAULT_CHECK_DISTANCE,
+    ));
+    GameplayPlugin::start_gameplay_session(
+        sessions,
+        session_runner,
+        Some(0),
+        BotPlayers::default(),
+        meta.gameplay.physics.clone(),
+        meta.gameplay.rules.clone(),
+        GameplaySprites::from_meta(&meta.gameplay),
+        None,
+        None,
+    );
+}
+
+/// Draws the result of the sync test so far, Escape prints it and returns to the menu
+pub fn draw_sync_test_report(
+    mut sessions: ResMut<Sessions>,
+    mut session_options: ResMut<SessionOptions>,
+    keyboard: Res<KeyboardInputs>,
+    ctx: Res<EguiCtx>,
+) {
+    let Some(report) = sessions
+        .get(SessionNames::GAMEPLAY)
+        .and_then(|session| session.world.get_resource::<SyncTestReport>())
+        .map(|report| report.clone())
+    else {
+        return;
+    };
+
+    let escape_pressed = keyboard
+        .key_events
+        .iter()
+        .any(|event| event.key_code == Set(KeyCode::Escape) && event.button_state.pressed());
+    if escape_pressed {
+        // The result stays available on the command line once the window is gone
+        println!("{}", report.summary());
+        return_to_main_menu(&mut sessions, &mut session_options);
+        return;
+    }
+
+    egui::Area::new("sync_test_report")
+        .anchor(egui::Align2::CENTER_BOTTOM, (0.0, -20.0))
+        .show(&ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                let (state, color) = match &report.mismatch {
+                    Some((original, resimulated)) => (
+                        format!(
+                            "MISMATCH at frame {}: {:#018x} vs {:#018x}",
+                            original.frame, original.checksum, resimulated.checksum
+                        ),
+                        Color32::RED,
+                    ),
+                    None => ("no mismatch".to_string(), Color32::GREEN),
+                };
+                let text = RichText::new(format!(
+                    "SYNC TEST  {} frames checked  {}",
+                    report.frames_checked, state
+                ))
+                .color(color)
+                .size(24.0)
+                .strong();
+                ui.label(text);
+                let text = RichText::new("Escape: Leave")
+                    .color(Color32::WHITE)
+                    .size(16.0);
+                ui.label(text);
+            });
+        });
+}
@@ -0,0 +1,124 @@
+use crate::gameplay::gameplay_fixed_math::isqrt;
+use crate::gameplay::{Fixed, FixedVec2};
+use std::f32::consts::PI;
+
+/// Asserts that the value is within `tolerance` of the expected float
+fn assert_close(value: Fixed, expected: f32, tolerance: f32) {
+    assert!(
+        (value.to_f32() - expected).abs() <= tolerance,
+        "{} isn't within {} of {}",
+        value.to_f32(),
+        tolerance,
+        expected
+    );
+}
+
+#[test]
+fn isqrt_rounds_down() {
+    assert_eq!(isqrt(0), 0);
+    assert_eq!(isqrt(1), 1);
+    assert_eq!(isqrt(15), 3);
+    assert_eq!(isqrt(16), 4);
+    assert_eq!(isqrt(17), 4);
+    assert_eq!(isqrt(u64::MAX as u128 * u64::MAX as u128), u64::MAX as u128);
+    assert_eq!(isqrt(u128::MAX), u64::MAX as u128);
+}
+
+#[test]
+fn sqrt_of_known_values() {
+    assert_eq!(Fixed::from_int(4).sqrt(), Fixed::from_int(2));
+    assert_eq!(Fixed::from_f32(0.25).sqrt(), Fixed::from_f32(0.5));
+    assert_close(Fixed::from_int(2).sqrt(), 2f32.sqrt(), 0.0001);
+    assert_close(Fixed::from_int(10_000).sqrt(), 100.0, 0.0001);
+}
+
+#[test]
+fn sqrt_of_zero_and_negative_values_is_zero() {
+    assert_eq!(Fixed::ZERO.sqrt(), Fixed::ZERO);
+    assert_eq!(Fixed::from_int(-4).sqrt(), Fixed::ZERO);
+    assert_eq!(Fixed::from_bits(-1).sqrt(), Fixed::ZERO);
+}
+
+#[test]
+fn sin_of_known_values() {
+    assert_eq!(Fixed::ZERO.sin(), Fixed::ZERO);
+    assert_close(Fixed::FRAC_PI_2.sin(), 1.0, 0.001);
+    assert_close((-Fixed::FRAC_PI_2).sin(), -1.0, 0.001);
+    assert_close(Fixed::PI.sin(), 0.0, 0.001);
+    assert_close(Fixed::from_f32(PI / 6.0).sin(), 0.5, 0.001);
+    assert_close(Fixed::from_f32(-PI / 6.0).sin(), -0.5, 0.001);
+}
+
+#[test]
+fn sin_wraps_angles_outside_of_a_turn() {
+    assert_close(Fixed::from_int(10).sin(), 10f32.sin(), 0.001);
+    assert_close(Fixed::from_int(-10).sin(), (-10f32).sin(), 0.001);
+}
+
+#[test]
+fn cos_of_known_values() {
+    assert_close(Fixed::ZERO.cos(), 1.0, 0.001);
+    assert_close(Fixed::FRAC_PI_2.cos(), 0.0, 0.001);
+    assert_close(Fixed::PI.cos(), -1.0, 0.001);
+    assert_close(Fixed::from_f32(-PI / 3.0).cos(), 0.5, 0.001);
+}
+
+#[test]
+fn div_of_known_values() {
+    assert_eq!(Fixed::ONE / Fixed::from_int(2), Fixed::from_f32(0.5));
+    assert_eq!(
+        Fixed::from_int(-3) / Fixed::from_int(2),
+        Fixed::from_f32(-1.5)
+    );
+    assert_eq!(
+        Fixed::from_int(7) / Fixed::from_int(-2),
+        Fixed::from_f32(-3.5)
+    );
+    assert_eq!(Fixed::ZERO / Fixed::from_int(5), Fixed::ZERO);
+    // Rounds towards zero
+    assert_eq!((Fixed::ONE / Fixed::from_int(3)).to_bits(), 21_845);
+}
+
+#[test]
+fn checked_div_by_zero_is_none() {
+    assert_eq!(Fixed::ONE.checked_div(Fixed::ZERO), None);
+    assert_eq!(Fixed::ZERO.checked_div(Fixed::ZERO), None);
+    assert_eq!(
+        Fixed::ONE.checked_div(Fixed::from_int(4)),
+        Some(Fixed::from_f32(0.25))
+    );
+}
+
+#[test]
+#[should_panic]
+fn div_by_zero_panics() {
+    let _ = Fixed::ONE / Fixed::ZERO;
+}
+
+#[test]
+fn clamp_length_max_scales_down_longer_vectors() {
+    let vector = FixedVec2::new(Fixed::from_int(3), Fixed::from_int(4));
+    assert_eq!(
+        vector.clamp_length_max(Fixed::from_int(10)),
+        vector,
+        "shorter vectors are unchanged"
+    );
+    let clamped = vector.clamp_length_max(Fixed::from_f32(2.5));
+    assert_close(clamped.x, 1.5, 0.001);
+    assert_close(clamped.y, 2.0, 0.001);
+}
+
+#[test]
+fn clamp_length_max_keeps_vectors_without_a_positive_max() {
+    let vector = FixedVec2::new(Fixed::from_int(3), Fixed::from_int(4));
+    assert_eq!(vector.clamp_length_max(Fixed::ZERO), vector);
+    assert_eq!(vector.clamp_length_max(-Fixed::ONE), vector);
+    assert_eq!(
+        FixedVec2::ZERO.clamp_length_max(Fixed::ZERO),
+        FixedVec2::ZERO
+    );
+    assert_eq!(
+        FixedVec2::ZERO.clamp_length_max(-Fixed::ONE),
+        FixedVec2::ZERO
+    );
+}
@@ -0,0 +1,372 @@
+use super::harness::{hold_direction, GameplayHarness};
+use crate::gameplay::{
+    handle_leave_match, ConnectionMonitor, Fixed, MatchPhase, MatchRules, PauseState, PhysicsMeta,
+    Player, RallyHistory, RematchVotes,
+};
+use crate::input::DensePlayerControl;
+use crate::networking::MatchSocket;
+use crate::SessionNames;
+use bones_framework::prelude::*;
+
+#[test]
+fn ball_landing_on_left_side_scores_for_right_player() {
+    let mut harness = GameplayHarness::new();
+    harness.start_rally();
+    // Far enough from the left player to not be touched on the way down
+    harness.set_ball(vec2(-450.0, -240.0), Vec2::ZERO);
+
+    assert!(harness.step_until(60, [default(); 2], |harness| {
+        harness.match_state().get_player_score(1) == 1
+    }));
+    assert_eq!(harness.match_state().get_player_score(0), 0);
+    assert!(matches!(
+        harness.match_phase(),
+        MatchPhase::PointScored { scorer: 1, .. }
+    ));
+
+    // After the pause the winner of the rally holds the ball for the next serve
+    assert!(harness.step_until(600, [default(); 2], |harness| {
+        matches!(harness.match_phase(), MatchPhase::Serving { .. })
+    }));
+    assert_eq!(harness.match_state().server(), 1);
+    assert_eq!(harness.ball().position.x, harness.player(1).position.x);
+}
+
+#[test]
+fn ball_landing_on_right_side_scores_for_left_player() {
+    let mut harness = GameplayHarness::new();
+    harness.start_rally();
+    harness.set_ball(vec2(450.0, -240.0), Vec2::ZERO);
+
+    assert!(harness.step_until(60, [default(); 2], |harness| {
+        harness.match_state().get_player_score(0) == 1
+    }));
+    assert_eq!(harness.match_state().get_player_score(1), 0);
+    assert_eq!(harness.match_state().server(), 0);
+}
+
+#[test]
+fn players_are_clamped_to_the_outer_boundaries() {
+    let mut harness = GameplayHarness::new();
+    harness.start_rally();
+    let physics = PhysicsMeta::default().to_fixed();
+    let half_width = physics.player_width / Fixed::from_int(2);
+
+    harness.step_frames(200, [hold_direction(-1.0), hold_direction(1.0)]);
+
+    assert_eq!(
+        harness.player(0).position.x,
+        physics.left_boundary + half_width
+    );
+    assert_eq!(
+        harness.player(1).position.x,
+        physics.right_boundary - half_width
+    );
+}
+
+#[test]
+fn players_are_clamped_to_their_side_of_the_net() {
+    let mut harness = GameplayHarness::new();
+    harness.start_rally();
+    let physics = PhysicsMeta::default().to_fixed();
+    let half_width = physics.player_width / Fixed::from_int(2);
+
+    harness.step_frames(200, [hold_direction(1.0), hold_direction(-1.0)]);
+
+    assert_eq!(
+        harness.player(0).position.x,
+        physics.center_boundary - half_width - physics.net_width
+    );
+    assert_eq!(
+        harness.player(1).position.x,
+        physics.center_boundary + physics.net_width + half_width
+    );
+}
+
+#[test]
+fn ball_falling_on_top_of_the_net_bounces_up() {
+    let mut harness = GameplayHarness::new();
+    harness.start_rally();
+    let physics = PhysicsMeta::default().to_fixed();
+    // Just above the top of the net (base -259, height 62, ball radius 10)
+    harness.set_ball(vec2(0.0, -183.0), vec2(0.0, -5.0));
+
+    harness.step([default(); 2]);
+
+    let ball = harness.ball();
+    assert!(ball.velocity.y > Fixed::ZERO);
+    assert_eq!(ball.velocity.x, Fixed::ZERO);
+    assert_eq!(
+        ball.position.y,
+        physics.net_base_level + physics.net_height + physics.ball_radius
+    );
+}
+
+#[test]
+fn ball_hitting_the_side_of_the_net_bounces_back() {
+    let mut harness = GameplayHarness::new();
+    harness.start_rally();
+    let physics = PhysicsMeta::default().to_fixed();
+    // Moving right into the left side of the net, well below its top
+    harness.set_ball(vec2(-19.0, -240.0), vec2(5.0, 0.0));
+
+    harness.step([default(); 2]);
+
+    let ball = harness.ball();
+    assert!(ball.velocity.x < Fixed::ZERO);
+    let offset = physics.net_width / Fixed::from_int(2) + physics.ball_radius + Fixed::ONE;
+    assert_eq!(ball.position.x, physics.center_boundary - offset);
+    assert_eq!(harness.match_state().get_player_score(0), 0);
+    assert_eq!(harness.match_state().get_player_score(1), 0);
+}
+
+#[test]
+fn ball_is_held_above_the_server_until_served() {
+    let mut harness = GameplayHarness::new();
+    let rules = MatchRules::default();
+
+    // Pre-match countdown, then the serve timer runs out while the server walks around
+    harness.step_frames(rules.countdown_frames as usize, [default(); 2]);
+    assert!(matches!(harness.match_phase(), MatchPhase::Serving { .. }));
+    harness.step_frames(30, [hold_direction(1.0), default()]);
+    assert_eq!(harness.ball().position.x, harness.player(0).position.x);
+    assert_eq!(harness.ball().velocity, Default::default());
+
+    assert!(harness.step_until(
+        rules.serve_timer_frames as usize,
+        [default(); 2],
+        |harness| { harness.match_phase() == MatchPhase::Rally }
+    ));
+    assert!(harness.ball().velocity.y > Fixed::ZERO);
+}
+
+#[test]
+fn winning_point_ends_the_match() {
+    let mut harness = GameplayHarness::with_config(
+        PhysicsMeta::default(),
+        MatchRules {
+            target_score: 1,
+            ..Default::default()
+        },
+    );
+    harness.start_rally();
+    harness.set_ball(vec2(450.0, -240.0), Vec2::ZERO);
+
+    assert!(harness.step_until(60, [default(); 2], |harness| {
+        harness.match_state().is_finished()
+    }));
+    assert_eq!(harness.match_phase(), MatchPhase::MatchOver { winner: 0 });
+
+    // Nothing moves anymore
+    let ball = harness.ball();
+    harness.step_frames(60, [hold_direction(1.0), hold_direction(-1.0)]);
+    assert_eq!(harness.ball().position, ball.position);
+}
+
+#[test]
+fn rematch_restarts_the_match_once_both_players_voted() {
+    let mut harness = GameplayHarness::with_config(
+        PhysicsMeta::default(),
+        MatchRules {
+            target_score: 1,
+            ..Default::default()
+        },
+    );
+    harness.start_rally();
+    harness.set_ball(vec2(450.0, -240.0), Vec2::ZERO);
+    assert!(harness.step_until(60, [default(); 2], |harness| {
+        harness.match_state().is_finished()
+    }));
+    harness.step([default(); 2]);
+
+    // A single vote isn't enough
+    let jump = DensePlayerControl::new(Vec2::ZERO, true, false, false);
+    harness.step([jump, default()]);
+    harness.step([default(); 2]);
+    assert!(harness.match_state().is_finished());
+
+    harness.step([default(), jump]);
+    assert!(!harness.match_state().is_finished());
+    assert_eq!(harness.match_state().get_player_score(0), 0);
+    assert!(matches!(harness.match_phase(), MatchPhase::PreMatch { .. }));
+    let physics = PhysicsMeta::default().to_fixed();
+    assert_eq!(
+        harness.player(0).position,
+        Player::spawn_position(0, &physics)
+    );
+    assert_eq!(harness.ball().position.x, -physics.spawn_offset_x);
+}
+
+#[test]
+fn leaving_after_the_match_returns_to_the_menu() {
+    let mut harness = GameplayHarness::with_config(
+        PhysicsMeta::default(),
+        MatchRules {
+            target_score: 1,
+            ..Default::default()
+        },
+    );
+    harness.start_rally();
+    harness.set_ball(vec2(450.0, -240.0), Vec2::ZERO);
+    assert!(harness.step_until(60, [default(); 2], |harness| {
+        harness.match_state().is_finished()
+    }));
+    harness.step([default(); 2]);
+
+    // Escape opens the pause menu, confirming "Leave match?" isn't a rematch vote
+    let escape = DensePlayerControl::new(Vec2::ZERO, false, true, false);
+    let jump = DensePlayerControl::new(Vec2::ZERO, true, false, false);
+    harness.step([escape, default()]);
+    assert_eq!(harness.match_phase(), MatchPhase::Paused);
+    harness.step([hold_direction(-1.0), default()]);
+    harness.step([jump, default()]);
+    let pause_state = harness.session.world.resource::<PauseState>().clone();
+    assert!(pause_state.leave_confirmed_at.is_some());
+    assert_eq!(
+        harness.session.world.resource::<RematchVotes>().votes,
+        [false; 2]
+    );
+
+    // Without a socket the gameplay_ui session goes straight back to the menu
+    let mut sessions = Sessions::default();
+    *sessions.create(SessionNames::GAMEPLAY) = harness.session;
+    let mut ui_world = World::default();
+    ui_world.insert_resource(sessions);
+    ui_world.init_resource::<SessionOptions>();
+    ui_world.init_resource::<MatchSocket>();
+    ui_world.init_resource::<ConnectionMonitor>();
+    ui_world.run_system(handle_leave_match, ());
+
+    let sessions = ui_world.resource::<Sessions>();
+    assert!(sessions.get(SessionNames::GAMEPLAY).is_none());
+    assert!(sessions.get(SessionNames::MAIN_MENU).is_some());
+    assert!(ui_world.resource::<SessionOptions>().delete);
+}
+
+#[test]
+fn pause_freezes_the_match_until_the_pausing_player_resumes() {
+    let mut harness = GameplayHarness::new();
+    harness.start_rally();
+    let escape = DensePlayerControl::new(Vec2::ZERO, false, true, false);
+    harness.step([escape, default()]);
+    assert_eq!(harness.match_phase(), MatchPhase::Paused);
+    assert_eq!(
+        harness.session.world.resource::<PauseState>().paused_by,
+        Some(0)
+    );
+
+    // Nothing moves and only the player who paused can resume
+    let ball = harness.ball();
+    harness.step_frames(30, [default(), escape]);
+    harness.step_frames(30, [hold_direction(1.0), default()]);
+    assert_eq!(harness.ball().position, ball.position);
+    assert_eq!(harness.match_phase(), MatchPhase::Paused);
+
+    harness.step([default(); 2]);
+    harness.step([escape, default()]);
+    assert_eq!(harness.match_phase(), MatchPhase::Rally);
+}
+
+#[test]
+fn pause_resumes_by_itself_after_the_timeout() {
+    let rules = MatchRules {
+        pause_timeout_frames: 120,
+        ..Default::default()
+    };
+    let mut harness = GameplayHarness::with_config(PhysicsMeta::default(), rules);
+    harness.start_rally();
+    let escape = DensePlayerControl::new(Vec2::ZERO, false, true, false);
+    harness.step([escape, default()]);
+    assert_eq!(harness.match_phase(), MatchPhase::Paused);
+
+    harness.step_frames(119, [default(); 2]);
+    assert_eq!(harness.match_phase(), MatchPhase::Paused);
+    harness.step([default(); 2]);
+    assert_eq!(harness.match_phase(), MatchPhase::Rally);
+    assert_eq!(
+        harness.session.world.resource::<PauseState>().paused_by,
+        None
+    );
+}
+
+#[test]
+fn instant_replay_shows_the_end_of_the_rally_after_a_point() {
+    let mut harness = GameplayHarness::new();
+    harness.start_rally();
+    let served_position = harness.ball().position.to_vec2();
+    harness.set_ball(vec2(-450.0, -240.0), Vec2::ZERO);
+    assert!(harness.step_until(60, [default(); 2], |harness| {
+        harness.match_state().get_player_score(1) == 1
+    }));
+
+    // The replay starts at the serve, while the ball stays where it landed
+    let rules = MatchRules::default();
+    assert!(harness.step_until(
+        rules.countdown_frames as usize,
+        [default(); 2],
+        |harness| matches!(harness.match_phase(), MatchPhase::InstantReplay { .. })
+    ));
+    let replay_ball = |harness: &GameplayHarness| {
+        let MatchPhase::InstantReplay { frames_left } = harness.match_phase() else {
+            panic!("the instant replay is shown");
+        };
+        let rally_history = harness.session.world.resource::<RallyHistory>();
+        let (_players, ball) = rally_history
+            .replay_positions(&rules, frames_left)
+            .expect("the rally was recorded");
+        ball
+    };
+    assert_eq!(replay_ball(&harness), served_position);
+    assert_ne!(harness.ball().position.to_vec2(), served_position);
+    // Only what is rendered is replayed, the simulation keeps its state
+    assert_eq!(
+        harness.ball_translation().truncate(),
+        harness.ball().position.to_vec2()
+    );
+
+    // The short rally is over before the replay, which holds its last frame
+    harness.step_frames(rules.instant_replay_frames() as usize - 1, [default(); 2]);
+    assert!(matches!(
+        harness.match_phase(),
+        MatchPhase::InstantReplay { frames_left: 1 }
+    ));
+    assert_eq!(replay_ball(&harness), harness.ball().position.to_vec2());
+    harness.step([default(); 2]);
+    assert!(matches!(harness.match_phase(), MatchPhase::Serving { .. }));
+}
+
+#[test]
+fn no_instant_replay_is_shown_without_rally_replay_frames() {
+    let rules = MatchRules {
+        rally_replay_frames: 0,
+        ..Default::default()
+    };
+    let mut harness = GameplayHarness::with_config(PhysicsMeta::default(), rules.clone());
+    harness.start_rally();
+    harness.set_ball(vec2(-450.0, -240.0), Vec2::ZERO);
+    assert!(harness.step_until(60, [default(); 2], |harness| {
+        matches!(harness.match_phase(), MatchPhase::PointScored { .. })
+    }));
+    assert!(harness.step_until(
+        rules.countdown_frames as usize,
+        [default(); 2],
+        |harness| matches!(harness.match_phase(), MatchPhase::Serving { .. })
+    ));
+}
+
+#[test]
+fn jumping_skips_the_instant_replay_without_serving() {
+    let mut harness = GameplayHarness::new();
+    harness.start_rally();
+    harness.set_ball(vec2(-450.0, -240.0), Vec2::ZERO);
+    assert!(harness.step_until(200, [default(); 2], |harness| {
+        matches!(harness.match_phase(), MatchPhase::InstantReplay { .. })
+    }));
+
+    // The right player scored, so they serve next
+    let jump = DensePlayerControl::new(Vec2::ZERO, true, false, false);
+    harness.step([default(), jump]);
+    assert!(matches!(harness.match_phase(), MatchPhase::Serving { .. }));
+    assert_eq!(harness.ball().position.x, harness.player(1).position.x);
+    assert_eq!(harness.ball().velocity, Default::default());
+}
@@ -0,0 +1,222 @@
+use crate::cli::CliArgs;
+use crate::gameplay::{
+    Ball, BotPlayers, FixedVec2, GameplayPlugin, MatchPhase, MatchRules, MatchState, PhysicsMeta,
+    Player,
+};
+use crate::input::{DensePlayerControl, MatchInputs};
+use crate::networking::FPS;
+use bones_framework::input::PlayerControls;
+use bones_framework::networking::input::NetworkPlayerControl;
+use bones_framework::prelude::*;
+
+/// Creates a gameplay session which doesn't depend on any other session, asset or socket
+pub fn headless_gameplay_session(
+    session_runner: Box<dyn SessionRunner>,
+    physics: PhysicsMeta,
+    rules: MatchRules,
+) -> Session {
+    headless_match_session(session_runner, default(), physics, rules, None)
+}
+
+/// Creates a headless gameplay session with the given bots, which records a replay if
+/// `replay_input_delay` is set
+pub fn headless_match_session(
+    session_runner: Box<dyn SessionRunner>,
+    bot_players: BotPlayers,
+    physics: PhysicsMeta,
+    rules: MatchRules,
+    replay_input_delay: Option<usize>,
+) -> Session {
+    let mut session = Session::new();
+    setup_headless_match_session(
+        &mut session,
+        session_runner,
+        bot_players,
+        physics,
+        rules,
+        replay_input_delay,
+    );
+    session
+}
+
+/// Sets up the given session like `headless_match_session`, e.g. one created through `Sessions`
+pub fn setup_headless_match_session(
+    session: &mut Session,
+    session_runner: Box<dyn SessionRunner>,
+    bot_players: BotPlayers,
+    physics: PhysicsMeta,
+    rules: MatchRules,
+    replay_input_delay: Option<usize>,
+) {
+    // Resources which are normally shared by the game
+    session.world.init_resource::<Time>();
+    session.world.init_resource::<Sessions>();
+    session.world.init_resource::<SessionOptions>();
+    session.world.init_resource::<CliArgs>();
+
+    GameplayPlugin::setup_gameplay_session(
+        session,
+        session_runner,
+        Some(0),
+        bot_players,
+        physics,
+        rules,
+        default(),
+        replay_input_delay,
+    );
+}
+
+/// Steps a headless gameplay session one frame at a time with scripted inputs
+pub struct GameplayHarness {
+    pub session: Session,
+}
+
+impl GameplayHarness {
+    /// Creates a harness with the default physics and rules, the startup systems and a first
+    /// frame have already run so every entity exists.
+    pub fn new() -> Self {
+        Self::with_config(PhysicsMeta::default(), MatchRules::default())
+    }
+
+    /// Creates a harness with the given physics and rules
+    pub fn with_config(physics: PhysicsMeta, rules: MatchRules) -> Self {
+        let session =
+            headless_gameplay_session(Box::<DefaultSessionRunner>::default(), physics, rules);
+        let mut harness = Self { session };
+        harness.step([default(); 2]);
+        harness
+    }
+
+    /// Simulates a single frame with the given inputs for both players
+    pub fn step(&mut self, inputs: [DensePlayerControl; 2]) {
+        let world = &mut self.session.world;
+        {
+            let mut match_inputs = world.resource_mut::<MatchInputs>();
+            for (player_idx, dense) in inputs.iter().enumerate() {
+                match_inputs
+                    .get_control_mut(player_idx)
+                    .update_from_dense(dense);
+            }
+        }
+        world
+            .resource_mut::<Time>()
+            .advance_exact(std::time::Duration::from_secs_f64(1.0 / FPS as f64));
+        self.session.stages.run(world);
+    }
+
+    /// Simulates the given number of frames with the same inputs
+    pub fn step_frames(&mut self, frames: usize, inputs: [DensePlayerControl; 2]) {
+        for _ in 0..frames {
+            self.step(inputs);
+        }
+    }
+
+    /// Simulates frames with the given inputs until the condition holds, returns whether it did
+    /// within `max_frames`
+    pub fn step_until(
+        &mut self,
+        max_frames: usize,
+        inputs: [DensePlayerControl; 2],
+        mut condition: impl FnMut(&Self) -> bool,
+    ) -> bool {
+        for _ in 0..max_frames {
+            self.step(inputs);
+            if condition(self) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Simulates frames until the countdown is over, then serves by jumping with the server
+    pub fn start_rally(&mut self) {
+        let mut inputs = [DensePlayerControl::default(); 2];
+        self.step_until(600, inputs, |harness| {
+            matches!(harness.match_phase(), MatchPhase::Serving { .. })
+        });
+        inputs[self.match_state().server()] =
+            DensePlayerControl::new(Vec2::ZERO, true, false, false);
+        self.step(inputs);
+        assert_eq!(self.match_phase(), MatchPhase::Rally);
+    }
+
+    /// Returns the current match state
+    pub fn match_state(&self) -> MatchState {
+        self.session.world.resource::<MatchState>().clone()
+    }
+
+    /// Returns the current match phase
+    pub fn match_phase(&self) -> MatchPhase {
+        *self.session.world.resource::<MatchPhase>()
+    }
+
+    /// Returns the player with the given index
+    pub fn player(&self, player_idx: usize) -> Player {
+        self.session.world.run_system(
+            |entities: Res<Entities>, players: Comp<Player>| {
+                entities
+                    .iter_with(&players)
+                    .map(|(_ent, player)| player.clone())
+                    .find(|player| player.idx == player_idx)
+                    .expect("player exists")
+            },
+            (),
+        )
+    }
+
+    /// Returns the ball
+    pub fn ball(&self) -> Ball {
+        self.session.world.run_system(
+            |entities: Res<Entities>, balls: Comp<Ball>| {
+                let (_ent, ball) = entities.iter_with(&balls).next().expect("ball exists");
+                ball.clone()
+            },
+            (),
+        )
+    }
+
+    /// Returns the rendered position of the ball
+    pub fn ball_translation(&self) -> Vec3 {
+        self.session.world.run_system(
+            |entities: Res<Entities>, balls: Comp<Ball>, transforms: Comp<Transform>| {
+                let (_ent, (_ball, transform)) = entities
+                    .iter_with((&balls, &transforms))
+                    .next()
+                    .expect("ball exists");
+                transform.translation
+            },
+            (),
+        )
+    }
+
+    /// Returns the rendered position of every entity, in the order they were spawned
+    pub fn translations(&self) -> Vec<Vec3> {
+        self.session.world.run_system(
+            |entities: Res<Entities>, transforms: Comp<Transform>| {
+                entities
+                    .iter_with(&transforms)
+                    .map(|(_ent, transform)| transform.translation)
+                    .collect()
+            },
+            (),
+        )
+    }
+
+    /// Places the ball at the given position (in simulation units) with the given velocity
+    pub fn set_ball(&mut self, position: Vec2, velocity: Vec2) {
+        self.session.world.run_system(
+            |entities: Res<Entities>, mut balls: CompMut<Ball>| {
+                for (_ent, ball) in entities.iter_with(&mut balls) {
+                    ball.position = FixedVec2::from_f32(position.x, position.y);
+                    ball.velocity = FixedVec2::from_f32(velocity.x, velocity.y);
+                }
+            },
+            (),
+        );
+    }
+}
+
+/// Inputs of a player holding the given horizontal direction
+pub fn hold_direction(direction: f32) -> DensePlayerControl {
+    DensePlayerControl::new(Vec2::new(direction, 0.0), false, false, false)
+}
@@ -0,0 +1,168 @@
+use crate::gameplay::{MatchRules, MatchState};
+
+/// Creates a single set match state with the given target score and default rules
+fn match_with_target_score(target_score: u32) -> MatchState {
+    MatchState::new(MatchRules {
+        target_score,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn no_winner_before_target_score() {
+    let mut match_state = match_with_target_score(3);
+    match_state.award_point(0);
+    match_state.award_point(0);
+    match_state.award_point(1);
+
+    assert_eq!(match_state.check_for_match_winner(), None);
+    assert!(!match_state.is_finished());
+}
+
+#[test]
+fn player_reaching_target_score_wins() {
+    let mut match_state = match_with_target_score(3);
+    for _ in 0..3 {
+        match_state.award_point(1);
+    }
+    match_state.award_point(0);
+
+    assert_eq!(match_state.check_for_match_winner(), Some(1));
+    assert!(match_state.is_finished());
+}
+
+#[test]
+fn fourth_touch_on_one_side_gives_opponent_the_point() {
+    let mut match_state = match_with_target_score(15);
+    for _ in 0..3 {
+        assert_eq!(match_state.register_touch(0), None);
+    }
+    assert_eq!(match_state.register_touch(0), Some(1));
+}
+
+#[test]
+fn touches_reset_when_the_ball_crosses_the_net() {
+    let mut match_state = match_with_target_score(15);
+    for _ in 0..3 {
+        assert_eq!(match_state.register_touch(0), None);
+    }
+    match_state.reset_touches();
+    assert_eq!(match_state.register_touch(1), None);
+    assert_eq!(match_state.touch_count(), 1);
+}
+
+#[test]
+fn touch_limit_can_be_disabled() {
+    let mut match_state = MatchState::new(MatchRules {
+        max_touches: 0,
+        ..Default::default()
+    });
+    for _ in 0..100 {
+        assert_eq!(match_state.register_touch(1), None);
+    }
+}
+
+#[test]
+fn double_touch_can_be_forbidden() {
+    let mut match_state = MatchState::new(MatchRules {
+        allow_double_touch: false,
+        ..Default::default()
+    });
+    assert_eq!(match_state.register_touch(1), None);
+    assert_eq!(match_state.register_touch(1), Some(0));
+}
+
+/// Awards the given number of points to the specified player
+fn award_points(match_state: &mut MatchState, player_idx: usize, points: u32) {
+    for _ in 0..points {
+        match_state.award_point(player_idx);
+    }
+}
+
+#[test]
+fn win_by_two_extends_the_set() {
+    let mut match_state = MatchState::new(MatchRules {
+        target_score: 5,
+        win_by_two: true,
+        ..Default::default()
+    });
+    award_points(&mut match_state, 0, 4);
+    award_points(&mut match_state, 1, 4);
+    match_state.award_point(0);
+    assert_eq!(match_state.check_for_match_winner(), None);
+
+    match_state.award_point(1);
+    match_state.award_point(1);
+    assert_eq!(match_state.check_for_match_winner(), None);
+
+    match_state.award_point(1);
+    assert_eq!(match_state.check_for_match_winner(), Some(1));
+    assert_eq!(match_state.get_player_score(1), 7);
+    assert_eq!(match_state.get_player_score(0), 5);
+}
+
+#[test]
+fn point_cap_ends_the_set_without_a_two_point_lead() {
+    let mut match_state = MatchState::new(MatchRules {
+        target_score: 5,
+        win_by_two: true,
+        point_cap: 7,
+        ..Default::default()
+    });
+    for _ in 0..6 {
+        match_state.award_point(0);
+        match_state.award_point(1);
+    }
+    assert_eq!(match_state.check_for_match_winner(), None);
+
+    match_state.award_point(0);
+    assert_eq!(match_state.check_for_match_winner(), Some(0));
+}
+
+#[test]
+fn best_of_three_sets_switches_sides_between_sets() {
+    let mut match_state = MatchState::new(MatchRules {
+        target_score: 3,
+        best_of_sets: 3,
+        ..Default::default()
+    });
+    assert_eq!(match_state.current_set(), 1);
+    assert_eq!(match_state.side_of(0), 0);
+
+    // Player 0 wins the first set, the points reset and the players switch sides
+    award_points(&mut match_state, 0, 3);
+    assert_eq!(match_state.get_set_score(0), 1);
+    assert_eq!(match_state.get_player_score(0), 0);
+    assert_eq!(match_state.current_set(), 2);
+    assert_eq!(match_state.side_of(0), 1);
+    assert_eq!(match_state.player_on_side(0), 1);
+    assert!(!match_state.is_finished());
+
+    // Player 1 wins the second set
+    award_points(&mut match_state, 1, 3);
+    assert_eq!(match_state.get_set_score(1), 1);
+    assert_eq!(match_state.side_of(0), 0);
+    assert!(!match_state.is_finished());
+
+    // Player 1 wins the deciding set, its points are kept for display
+    award_points(&mut match_state, 1, 3);
+    assert_eq!(match_state.check_for_match_winner(), Some(1));
+    assert_eq!(match_state.get_player_score(1), 3);
+    assert_eq!(match_state.current_set(), 3);
+    // The players stay on the sides they finished the match on
+    assert_eq!(match_state.side_of(0), 0);
+    assert_eq!(match_state.player_on_side(0), 0);
+}
+
+#[test]
+fn sides_are_kept_when_a_single_set_match_ends() {
+    let mut match_state = MatchState::new(MatchRules {
+        target_score: 3,
+        ..Default::default()
+    });
+    award_points(&mut match_state, 0, 3);
+    assert_eq!(match_state.check_for_match_winner(), Some(0));
+    assert_eq!(match_state.current_set(), 1);
+    assert_eq!(match_state.side_of(0), 0);
+    assert_eq!(match_state.player_on_side(0), 0);
+}
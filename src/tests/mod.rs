@@ -0,0 +1,12 @@
+//! Tests of the gameplay simulation, run headless without a window, renderer or network.
+
+mod harness;
+
+mod fixed_math;
+mod gameplay;
+mod match_state;
+mod network_simulator;
+mod networking;
+mod replay;
+mod settings;
+mod sync_test;
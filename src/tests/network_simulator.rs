@@ -0,0 +1,119 @@
+use crate::network_simulator::{DelayQueue, NetworkConditions, REORDER_DELAY};
+use bones_framework::prelude::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Creates a queue applying the given conditions, seeded like the sockets of a match
+fn delay_queue(conditions: NetworkConditions) -> DelayQueue<u32> {
+    DelayQueue::new(Arc::new(Mutex::new(conditions)))
+}
+
+/// Sends `count` numbered messages one millisecond apart, then polls the queue every millisecond
+/// and returns each message that arrived with its delay
+fn send_and_receive(queue: &mut DelayQueue<u32>, count: u32) -> Vec<(u32, Duration)> {
+    let start = Instant::now();
+    let millis = |ms: u32| start + Duration::from_millis(ms as u64);
+    let mut received = Vec::new();
+    for ms in 0..count + 1000 {
+        if ms < count {
+            queue.push(ms, millis(ms));
+        }
+        for message in queue.pop_due(millis(ms)) {
+            received.push((message, millis(ms) - millis(message)));
+        }
+    }
+    assert!(queue.is_empty());
+    received
+}
+
+#[test]
+fn perfect_conditions_deliver_everything_in_order_at_once() {
+    let mut queue = delay_queue(default());
+    let received = send_and_receive(&mut queue, 100);
+    assert_eq!(received.len(), 100);
+    for (idx, (message, delay)) in received.into_iter().enumerate() {
+        assert_eq!(message, idx as u32);
+        assert_eq!(delay, Duration::ZERO);
+    }
+}
+
+#[test]
+fn packet_loss_drops_the_configured_share_of_messages() {
+    let mut queue = delay_queue(NetworkConditions {
+        packet_loss: 0.25,
+        ..default()
+    });
+    let received = send_and_receive(&mut queue, 4000).len();
+    assert!((2880..=3120).contains(&received), "{} received", received);
+}
+
+#[test]
+fn latency_and_jitter_delay_messages_in_the_order_they_are_due() {
+    let mut queue = delay_queue(NetworkConditions {
+        latency_ms: 100,
+        jitter_ms: 30,
+        ..default()
+    });
+    let received = send_and_receive(&mut queue, 500);
+    assert_eq!(received.len(), 500);
+
+    let min_delay = Duration::from_millis(70);
+    let max_delay = Duration::from_millis(131);
+    assert!(received
+        .iter()
+        .all(|(_, delay)| (min_delay..=max_delay).contains(delay)));
+    // Jitter larger than the send interval lets later messages overtake earlier ones
+    assert!(received.windows(2).any(|pair| pair[1].0 < pair[0].0));
+
+    // Messages due at different times come out in due order even when polled late
+    let start = Instant::now();
+    let mut queue = delay_queue(NetworkConditions {
+        latency_ms: 100,
+        jitter_ms: 30,
+        ..default()
+    });
+    let mut due_order = Vec::new();
+    let mut reference = delay_queue(NetworkConditions {
+        latency_ms: 100,
+        jitter_ms: 30,
+        ..default()
+    });
+    for message in 0..50 {
+        queue.push(message, start);
+        reference.push(message, start);
+    }
+    for ms in 0..200 {
+        due_order.extend(reference.pop_due(start + Duration::from_millis(ms)));
+    }
+    assert_eq!(queue.pop_due(start + Duration::from_secs(1)), due_order);
+}
+
+#[test]
+fn reordered_messages_are_held_back_by_the_reorder_delay() {
+    let mut queue = delay_queue(NetworkConditions {
+        reorder: 0.2,
+        ..default()
+    });
+    let received = send_and_receive(&mut queue, 1000);
+    assert_eq!(received.len(), 1000);
+
+    let held_back = received
+        .iter()
+        .filter(|(_, delay)| *delay == REORDER_DELAY)
+        .count();
+    assert!(received
+        .iter()
+        .all(|(_, delay)| *delay == Duration::ZERO || *delay == REORDER_DELAY));
+    assert!((150..=250).contains(&held_back), "{} held back", held_back);
+
+    // The messages sent within the reorder delay after a held back one arrive before it
+    let (first_held_back, _) = received
+        .iter()
+        .find(|(_, delay)| *delay == REORDER_DELAY)
+        .unwrap();
+    let position = |message: u32| received.iter().position(|(m, _)| *m == message).unwrap();
+    let next = (first_held_back + 1..first_held_back + 40)
+        .find(|message| received[position(*message)].1 == Duration::ZERO)
+        .unwrap();
+    assert!(position(next) < position(*first_held_back));
+}
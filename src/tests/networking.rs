@@ -0,0 +1,152 @@
+use crate::gameplay::{GgrsConnectionState, MatchRules, PhysicsMeta};
+use crate::input::DensePlayerControl;
+use crate::networking::{
+    is_valid_node_id, is_valid_ticket, parse_node_id, recommended_input_delay, ReliableMessage,
+    MAX_INPUT_DELAY,
+};
+use crate::spectator::ConfirmedInputBuffer;
+use bones_framework::prelude::*;
+use std::time::Duration;
+
+#[test]
+fn node_ids_are_validated() {
+    assert!(is_valid_node_id(
+        "k4ff6u8ac7ebqn8qfpakmz3nq1tgtdwgq6by4zxtfs5qrsfbhr7o"
+    ));
+    // Too short, uppercase and characters outside of z-base-32
+    assert!(!is_valid_node_id(
+        "k4ff6u8ac7ebqn8qfpakmz3nq1tgtdwgq6by4zxtfs5q"
+    ));
+    assert!(!is_valid_node_id(
+        "K4FF6U8AC7EBQN8QFPAKMZ3NQ1TGTDWGQ6BY4ZXTFS5QRSFBHR7O"
+    ));
+    assert!(!is_valid_node_id(
+        "l4ff6u8ac7ebqn8qfpakmz3nq1tgtdwgq6by4zxtfs5qrsfbhr7o"
+    ));
+    assert!(!is_valid_node_id(""));
+}
+
+#[test]
+fn node_ids_are_parsed_without_surrounding_whitespace() {
+    let node_id = "k4ff6u8ac7ebqn8qfpakmz3nq1tgtdwgq6by4zxtfs5qrsfbhr7o";
+    assert_eq!(
+        parse_node_id::<String>(&format!(" {}\n", node_id)),
+        Ok(node_id.to_string())
+    );
+    assert!(parse_node_id::<String>("not a node id").is_err());
+}
+
+#[test]
+fn tickets_are_validated() {
+    assert!(is_valid_ticket("20b2d1c5f0e3a4"));
+    assert!(is_valid_ticket("20B2D1C5F0E3A4"));
+    // Hex bytes come in pairs
+    assert!(!is_valid_ticket("20b2d1c5f0e3a"));
+    assert!(!is_valid_ticket("20b2d1c5f0e3ag"));
+    assert!(!is_valid_ticket("20b2 d1c5"));
+    assert!(!is_valid_ticket(""));
+}
+
+#[test]
+fn input_delay_covers_half_the_round_trip() {
+    assert_eq!(recommended_input_delay(Duration::ZERO), 1);
+    // 50ms one way at 60 FPS is 3 frames
+    assert_eq!(recommended_input_delay(Duration::from_millis(100)), 3);
+    assert_eq!(recommended_input_delay(Duration::from_millis(101)), 4);
+    assert_eq!(
+        recommended_input_delay(Duration::from_secs(10)),
+        MAX_INPUT_DELAY
+    );
+}
+
+#[test]
+fn handshake_messages_round_trip() {
+    for message in [
+        ReliableMessage::Ping(7),
+        ReliableMessage::Pong(u32::MAX),
+        ReliableMessage::InputDelay(3),
+    ] {
+        let decoded = ReliableMessage::from_bytes(&message.to_bytes()).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+    }
+    assert!(ReliableMessage::from_bytes(&[3, 1]).is_none());
+}
+
+#[test]
+fn match_configs_with_unplayable_physics_are_rejected() {
+    let config = |physics: PhysicsMeta| {
+        ReliableMessage::MatchConfig(physics, MatchRules::default()).to_bytes()
+    };
+    assert!(ReliableMessage::from_bytes(&config(PhysicsMeta::default())).is_some());
+    for physics in [
+        PhysicsMeta {
+            gravity: f32::NAN,
+            ..default()
+        },
+        PhysicsMeta {
+            right_boundary: f32::INFINITY,
+            ..default()
+        },
+        PhysicsMeta {
+            move_speed: 0.0,
+            ..default()
+        },
+        PhysicsMeta {
+            max_ball_speed: -1.0,
+            ..default()
+        },
+    ] {
+        assert!(!physics.is_valid());
+        assert!(ReliableMessage::from_bytes(&config(physics)).is_none());
+    }
+}
+
+#[test]
+fn spectator_messages_round_trip() {
+    let inputs = vec![
+        [
+            DensePlayerControl::new(Vec2::new(-1.0, 0.0), true, false, false),
+            DensePlayerControl::default(),
+        ],
+        [
+            DensePlayerControl::default(),
+            DensePlayerControl::new(Vec2::new(1.0, 0.0), false, false, true),
+        ],
+    ];
+    for message in [
+        ReliableMessage::PlayerName("Alex".to_string()),
+        ReliableMessage::PlayerNames(["Alex".to_string(), String::new()]),
+        ReliableMessage::ConfirmedInputs(120, inputs),
+        ReliableMessage::ConfirmedInputs(0, Vec::new()),
+    ] {
+        let decoded = ReliableMessage::from_bytes(&message.to_bytes()).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+    }
+    // Truncated names and partial frames are rejected
+    assert!(ReliableMessage::from_bytes(&[7, 5, 0, b'A']).is_none());
+    assert!(ReliableMessage::from_bytes(&[8, 0, 0, 0, 0, 1, 2, 3]).is_none());
+}
+
+#[test]
+fn confirmed_inputs_are_buffered_without_gaps() {
+    let jump = DensePlayerControl::new(Vec2::ZERO, true, false, false);
+    let mut buffer = ConfirmedInputBuffer::default();
+    assert!(buffer.receive(0, &[[jump; 2], [default(); 2]]));
+    // Frames already received are skipped
+    assert!(buffer.receive(1, &[[default(); 2], [jump; 2]]));
+    assert_eq!(buffer.len(), 3);
+    assert_eq!(buffer.inputs[2], [jump; 2]);
+    // Frames after a gap are refused
+    assert!(!buffer.receive(5, &[[jump; 2]]));
+    assert_eq!(buffer.len(), 3);
+}
+
+#[test]
+fn ggrs_silence_interrupts_then_disconnects() {
+    let state = |millis| GgrsConnectionState::from_silence(Duration::from_millis(millis));
+    assert_eq!(state(0), GgrsConnectionState::Connected);
+    assert_eq!(state(499), GgrsConnectionState::Connected);
+    assert_eq!(state(500), GgrsConnectionState::Interrupted);
+    assert_eq!(state(1999), GgrsConnectionState::Interrupted);
+    assert_eq!(state(2000), GgrsConnectionState::Disconnected);
+}
@@ -0,0 +1,248 @@
+use super::harness::{
+    headless_match_session, hold_direction, setup_headless_match_session, GameplayHarness,
+};
+use crate::gameplay::{
+    handle_leave_match, BotDifficultyMeta, BotPlayers, ConnectionMonitor, MatchPhase, MatchRules,
+    MatchState, PauseState, PhysicsMeta, ReplayRecorder,
+};
+use crate::input::DensePlayerControl;
+use crate::networking::{MatchSocket, FPS};
+use crate::replay::{crc32, Replay, ReplaySessionRunner, ReplayStatus};
+use crate::SessionNames;
+use bones_framework::prelude::*;
+
+/// Returns scripted inputs moving both players back and forth and jumping now and then
+fn scripted_inputs(frames: usize) -> Vec<[DensePlayerControl; 2]> {
+    (0..frames)
+        .map(|frame| {
+            let direction = if (frame / 40) % 2 == 0 { 1.0 } else { -1.0 };
+            let jump = frame % 25 == 0;
+            [
+                DensePlayerControl::new(Vec2::new(direction, 0.0), jump, false, false),
+                DensePlayerControl::new(Vec2::new(-direction, 0.0), !jump, false, false),
+            ]
+        })
+        .collect()
+}
+
+#[test]
+fn replays_survive_an_encode_and_decode() {
+    let mut rules = MatchRules::default();
+    rules.target_score = 7;
+    let difficulty = BotDifficultyMeta {
+        name: "Very Hard".to_string(),
+        reaction_delay_frames: 2,
+        prediction_error: 5.5,
+        max_speed: 1.0,
+    };
+    let bot_players = BotPlayers::with_bot(1, difficulty);
+    let mut replay = Replay::new(1, &PhysicsMeta::default(), rules.clone(), 3, &bot_players);
+    replay.forfeit_winner = Some(1);
+    replay.inputs = scripted_inputs(100);
+
+    let decoded = Replay::decode(&replay.encode()).expect("replay decodes");
+    assert_eq!(decoded.game_version, replay.game_version);
+    assert_eq!(decoded.physics_hash, PhysicsMeta::default().config_hash());
+    assert_eq!(decoded.rules, rules);
+    assert_eq!(decoded.input_delay, 3);
+    assert_eq!(decoded.local_player_idx, 1);
+    assert!(decoded.bots[0].is_none());
+    let bot = decoded.bots[1].as_ref().expect("bot is kept");
+    assert_eq!(bot.name, "Very Hard");
+    assert_eq!(bot.reaction_delay_frames, 2);
+    assert_eq!(bot.prediction_error, 5.5);
+    assert_eq!(decoded.forfeit_winner, Some(1));
+    assert_eq!(decoded.inputs, replay.inputs);
+    assert_eq!(
+        Replay::decode(&Replay::default().encode())
+            .unwrap()
+            .forfeit_winner,
+        None
+    );
+}
+
+#[test]
+fn repeated_inputs_are_run_length_encoded() {
+    let mut replay = Replay::default();
+    let empty_len = replay.encode().len();
+    replay.inputs = vec![[DensePlayerControl::default(); 2]; 10_000];
+    assert!(replay.encode().len() < empty_len + 16);
+    assert_eq!(
+        Replay::decode(&replay.encode()).unwrap().inputs.len(),
+        10_000
+    );
+}
+
+#[test]
+fn playing_a_replay_reproduces_the_match() {
+    // Record a match of a scripted player against a bot
+    let difficulty = BotDifficultyMeta {
+        name: "Hard".to_string(),
+        reaction_delay_frames: 3,
+        prediction_error: 10.0,
+        max_speed: 1.0,
+    };
+    let session = headless_match_session(
+        Box::<DefaultSessionRunner>::default(),
+        BotPlayers::with_bot(1, difficulty),
+        PhysicsMeta::default(),
+        MatchRules::default(),
+        Some(0),
+    );
+    let mut original = GameplayHarness { session };
+    for inputs in scripted_inputs(1800) {
+        original.step(inputs);
+    }
+    let replay = original
+        .session
+        .world
+        .resource::<ReplayRecorder>()
+        .replay()
+        .expect("the match is recorded");
+    let replay = Replay::decode(&replay.encode()).expect("replay decodes");
+    assert_eq!(replay.inputs.len(), 1800);
+    assert!(replay.bots[1].is_some());
+    // The bot's inputs aren't recorded, it has to play the same way again
+    assert!(replay.inputs.iter().all(|inputs| inputs[1] == default()));
+
+    // Play it back through the replay runner, one frame time per step
+    let session = headless_match_session(
+        Box::new(ReplaySessionRunner::new(replay.clone())),
+        replay.bot_players(),
+        PhysicsMeta::default(),
+        replay.rules.clone(),
+        None,
+    );
+    let mut replayed = GameplayHarness { session };
+    let mut now = Instant::now();
+    let frame_time = std::time::Duration::from_secs_f64(1.0 / FPS as f64);
+    for _ in 0..2000 {
+        let session = &mut replayed.session;
+        session
+            .runner
+            .step(now, &mut session.world, &mut session.stages);
+        now += frame_time;
+    }
+    let status = replayed.session.world.resource::<ReplayStatus>().clone();
+    assert_eq!(status.frame, 1800);
+    assert_eq!(status.total_frames, 1800);
+
+    let original_state = original.match_state();
+    assert!(original_state.get_player_score(0) + original_state.get_player_score(1) > 0);
+    assert_eq!(
+        format!("{:?}", replayed.match_state()),
+        format!("{:?}", original_state)
+    );
+    assert_eq!(replayed.match_phase(), original.match_phase());
+    assert_eq!(replayed.translations(), original.translations());
+}
+
+#[test]
+fn replays_of_a_left_match_show_the_forfeit_at_the_end() {
+    // Record a match which player 0 leaves through the pause menu
+    let session = headless_match_session(
+        Box::<DefaultSessionRunner>::default(),
+        default(),
+        PhysicsMeta::default(),
+        MatchRules::default(),
+        Some(0),
+    );
+    let mut original = GameplayHarness { session };
+    let escape = DensePlayerControl::new(Vec2::ZERO, false, true, false);
+    let jump = DensePlayerControl::new(Vec2::ZERO, true, false, false);
+    original.step_frames(60, [default(); 2]);
+    original.step([escape, default()]);
+    original.step([hold_direction(-1.0), default()]);
+    original.step([jump, default()]);
+    let pause_state = original.session.world.resource::<PauseState>().clone();
+    assert!(pause_state.leave_confirmed_at.is_some());
+    let mut replay = original
+        .session
+        .world
+        .resource::<ReplayRecorder>()
+        .replay()
+        .expect("the match is recorded");
+    // The other peer saves the forfeit along with the recording
+    replay.forfeit_winner = Some(1);
+    let replay = Replay::decode(&replay.encode()).expect("replay decodes");
+
+    // Play it back to the end, with the gameplay_ui systems which act on a confirmed leave
+    let mut sessions = Sessions::default();
+    setup_headless_match_session(
+        sessions.create(SessionNames::GAMEPLAY),
+        Box::new(ReplaySessionRunner::new(replay.clone())),
+        replay.bot_players(),
+        PhysicsMeta::default(),
+        replay.rules.clone(),
+        None,
+    );
+    let mut ui_world = World::default();
+    ui_world.insert_resource(sessions);
+    ui_world.init_resource::<SessionOptions>();
+    ui_world.init_resource::<MatchSocket>();
+    ui_world.init_resource::<ConnectionMonitor>();
+    let mut now = Instant::now();
+    let frame_time = std::time::Duration::from_secs_f64(1.0 / FPS as f64);
+    for _ in 0..replay.inputs.len() + 10 {
+        {
+            let mut sessions = ui_world.resource_mut::<Sessions>();
+            let session = sessions
+                .get_mut(SessionNames::GAMEPLAY)
+                .expect("the replay keeps playing");
+            session
+                .runner
+                .step(now, &mut session.world, &mut session.stages);
+        }
+        ui_world.run_system(handle_leave_match, ());
+        now += frame_time;
+    }
+
+    let sessions = ui_world.resource::<Sessions>();
+    let session = sessions
+        .get(SessionNames::GAMEPLAY)
+        .expect("the replay isn't left with the recorded player");
+    let status = session.world.resource::<ReplayStatus>().clone();
+    assert_eq!(status.frame, replay.inputs.len());
+    assert_eq!(status.forfeit_winner, Some(1));
+    assert_eq!(*session.world.resource::<MatchPhase>(), MatchPhase::Paused);
+    assert_eq!(
+        session.world.resource::<MatchState>().forfeit_winner(),
+        Some(1)
+    );
+}
+
+#[test]
+fn corrupted_and_truncated_replays_are_rejected() {
+    assert!(Replay::decode(&[]).is_err());
+    assert!(Replay::decode(b"not a replay").is_err());
+
+    let mut replay = Replay::default();
+    replay.inputs = scripted_inputs(100);
+    let bytes = replay.encode();
+    for len in 0..bytes.len() {
+        assert!(Replay::decode(&bytes[..len]).is_err());
+    }
+    let mut corrupted = bytes.clone();
+    corrupted[bytes.len() / 2] ^= 0x10;
+    assert!(Replay::decode(&corrupted).is_err());
+}
+
+#[test]
+fn replays_of_other_builds_are_refused() {
+    let physics = PhysicsMeta::default();
+    let mut replay = Replay::new(0, &physics, default(), 0, &default());
+    assert!(replay.check_compatible(&physics).is_ok());
+
+    let mut other_physics = physics.clone();
+    other_physics.gravity *= 2.0;
+    assert!(replay.check_compatible(&other_physics).is_err());
+
+    replay.game_version = "0.0.0-other".to_string();
+    let error = replay.check_compatible(&physics).unwrap_err();
+    assert!(error.contains("0.0.0-other"));
+}
+
+#[test]
+fn crc32_matches_the_standard_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
@@ -0,0 +1,24 @@
+use crate::settings::MatchmakerSettings;
+
+const NODE_ID: &str = "k4ff6u8ac7ebqn8qfpakmz3nq1tgtdwgq6by4zxtfs5qrsfbhr7o";
+
+#[test]
+fn added_matchmakers_are_saved_and_selected() {
+    let mut settings = MatchmakerSettings::default();
+    assert_eq!(settings.server("default"), "default");
+
+    assert!(settings.add(&format!(" {} ", NODE_ID)).is_ok());
+    assert_eq!(settings.server("default"), NODE_ID);
+
+    // Adding it again doesn't duplicate it
+    assert!(settings.add(NODE_ID).is_ok());
+    assert_eq!(settings.servers.len(), 1);
+}
+
+#[test]
+fn invalid_matchmakers_are_rejected() {
+    let mut settings = MatchmakerSettings::default();
+    assert!(settings.add("not a node id").is_err());
+    assert!(settings.servers.is_empty());
+    assert_eq!(settings.server("default"), "default");
+}
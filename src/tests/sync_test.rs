@@ -0,0 +1,72 @@
+use super::harness::headless_gameplay_session;
+use crate::gameplay::{BotController, BotDifficultyMeta, BotPlayers, MatchRules, PhysicsMeta};
+use crate::sync_test::{
+    SyncTestInputs, SyncTestReport, SyncTestSessionRunner, DEFAULT_CHECK_DISTANCE,
+};
+use bones_framework::prelude::*;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Creates a headless gameplay session running under the sync test runner
+fn sync_test_session(inputs: SyncTestInputs) -> Session {
+    let runner = SyncTestSessionRunner::headless(inputs, DEFAULT_CHECK_DISTANCE);
+    headless_gameplay_session(
+        Box::new(runner),
+        PhysicsMeta::default(),
+        MatchRules::default(),
+    )
+}
+
+/// Steps the session for the given number of frames and returns the sync test report
+fn run_sync_test(mut session: Session, frames: u32) -> SyncTestReport {
+    for _ in 0..frames {
+        session
+            .runner
+            .step(Instant::now(), &mut session.world, &mut session.stages);
+    }
+    session.world.resource::<SyncTestReport>().clone()
+}
+
+#[test]
+fn random_inputs_resimulate_identically() {
+    for seed in [1, 42, 1337] {
+        let report = run_sync_test(sync_test_session(SyncTestInputs::Random { seed }), 600);
+        assert!(report.frames_checked > 0);
+        assert!(
+            report.mismatch.is_none(),
+            "seed {} desynced: {:?}",
+            seed,
+            report.mismatch
+        );
+    }
+}
+
+/// The number of times `non_rollback_safe_bot` ran, kept outside of the world so that rollbacks
+/// don't restore it
+static BOT_SYSTEM_RUNS: AtomicU32 = AtomicU32::new(0);
+
+/// Changes a bot depending on state outside of the world, like reading the clock would
+fn non_rollback_safe_bot(mut bot_players: ResMut<BotPlayers>) {
+    let runs = BOT_SYSTEM_RUNS.fetch_add(1, Ordering::Relaxed);
+    let difficulty = BotDifficultyMeta {
+        reaction_delay_frames: runs,
+        ..default()
+    };
+    bot_players.bots[1] = Some(BotController::new(difficulty));
+}
+
+#[test]
+fn non_rollback_safe_state_is_detected() {
+    let mut session = sync_test_session(SyncTestInputs::Random { seed: 1 });
+    session.add_system_to_stage(Update, non_rollback_safe_bot);
+
+    let report = run_sync_test(session, 10);
+    let (original, resimulated) = report
+        .mismatch
+        .clone()
+        .expect("the sync test reports a mismatch");
+    assert_eq!(original.frame, resimulated.frame);
+    assert_ne!(original.checksum, resimulated.checksum);
+    assert!(report
+        .summary()
+        .contains(&format!("mismatch at frame {}", original.frame)));
+}